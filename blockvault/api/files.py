@@ -1,6 +1,5 @@
 from __future__ import annotations
 
-import base64
 import io
 import os
 import time
@@ -9,15 +8,19 @@ import hashlib
 from typing import Dict, Any, List, Optional, Tuple
 
 from flask import Blueprint, request, abort, send_file
-from cryptography.hazmat.primitives import hashes, serialization
-from cryptography.hazmat.primitives.asymmetric import padding
 
 from ..core.security import require_auth
 from ..core.db import get_db
 from ..core.crypto_cli import (
+    CorruptedCiphertextError,
+    RangeNotSatisfiableError,
+    WrongPassphraseError,
     ensure_storage_dir,
     encrypt_file as crypto_encrypt,
     decrypt_file as crypto_decrypt,
+    read_metadata as crypto_read_metadata,
+    verify_file as crypto_verify,
+    wrap_key as crypto_wrap_key,
     generate_encrypted_filename,
 )
 from ..core import ipfs as ipfs_mod
@@ -92,11 +95,22 @@ def _maybe_get_file(file_id: str) -> Optional[Dict[str, Any]]:
     return None
 
 
-def _load_public_key(pem: str):
+def _display_fields(rec: Dict[str, Any], key: Optional[str]) -> Tuple[Optional[str], Optional[int]]:
+    """Name and plaintext size of a file.
+
+    Records carry both for listing; the few written without them are read
+    back from the blob's encrypted metadata, which needs the passphrase.
+    """
+    if rec.get("original_name") is not None:
+        return rec.get("original_name"), rec.get("size")
+    if not key:
+        return None, None
+    enc_path = ensure_storage_dir() / rec["enc_filename"]
     try:
-        return serialization.load_pem_public_key(pem.encode("utf-8"))
-    except Exception as exc:
-        abort(400, f"invalid recipient public key: {exc}")
+        metadata = crypto_read_metadata(enc_path, key)
+    except Exception:  # wrong passphrase, blob missing, or a blob without metadata
+        return None, None
+    return metadata.get("name"), metadata.get("size")
 
 
 def _serialize_share(doc: Dict[str, Any], include_encrypted: bool = True) -> Dict[str, Any]:
@@ -116,6 +130,8 @@ def _serialize_share(doc: Dict[str, Any], include_encrypted: bool = True) -> Dic
         base["file_size"] = doc.get("file_size")
     if "sha256" in doc:
         base["sha256"] = doc.get("sha256")
+    if "aad" in doc:
+        base["aad"] = doc.get("aad")
     if "cid" in doc:
         base["cid"] = doc.get("cid")
     if "gateway_url" in doc:
@@ -136,6 +152,8 @@ def upload_file():  # type: ignore
     if not key:
         abort(400, "key (passphrase) required")
     aad = request.form.get("aad") or None
+    pad = request.form.get("pad") or None  # padme | block:<n> | none
+    compress = request.form.get("compress") or None  # zstd[:level] | none
     folder = request.form.get("folder") or None
     if folder is not None:
         folder = folder.strip() or None
@@ -155,7 +173,13 @@ def upload_file():  # type: ignore
     enc_filename = generate_encrypted_filename()
     enc_path = storage_dir / enc_filename
     try:
-        crypto_encrypt(tmp_plain_path, enc_path, key, aad)
+        crypto_encrypt(tmp_plain_path, enc_path, key, aad, pad, compress, original_name, up_file.mimetype or None)
+    except RuntimeError as e:
+        if "invalid padding" in str(e):
+            abort(400, "pad must be padme, block:<n> or none")
+        if "invalid compression" in str(e):
+            abort(400, "compress must be zstd, zstd:<level> or none")
+        raise
     finally:
         if tmp_plain_path.exists():
             try:
@@ -171,10 +195,13 @@ def upload_file():  # type: ignore
         cid = None
 
     sha256 = hashlib.sha256(data).hexdigest()
+    # With padding the exact length must not leak: record and anchor the padded blob size.
+    padded = bool(pad) and pad != "none"
+    stored_size = enc_path.stat().st_size if padded else len(data)
 
     anchor_tx = None
     try:
-        anchor_tx = onchain_mod.anchor_file(sha256, len(data), cid)
+        anchor_tx = onchain_mod.anchor_file(sha256, stored_size, cid)
     except Exception:
         anchor_tx = None
 
@@ -182,7 +209,7 @@ def upload_file():  # type: ignore
         "owner": getattr(request, "address"),
         "original_name": original_name,
         "enc_filename": enc_filename,
-        "size": len(data),
+        "size": stored_size,
         # millisecond precision for better pagination granularity
         "created_at": int(time.time() * 1000),
         "aad": aad,
@@ -230,11 +257,28 @@ def download_file(file_id: str):  # type: ignore
         else:
             abort(410, "encrypted blob missing")
 
-    tmp_out = storage_dir / f"dec_{int(time.time()*1000)}_{rec['original_name']}"
+    # Single "bytes=START-END" / "bytes=START-" ranges decrypt only the covering
+    # segments; anything else (suffix or multi-range), or any range of a compressed
+    # blob, is served in full.
+    byte_range = None
+    range_header = (request.headers.get("Range") or "").strip()
+    if range_header.startswith("bytes="):
+        spec = range_header[len("bytes="):].strip()
+        if "," not in spec and not spec.startswith("-"):
+            byte_range = spec
+
+    tmp_out = storage_dir / f"dec_{int(time.time()*1000)}_{canonical_id}"
     try:
+        resolved = None
         try:
-            crypto_decrypt(enc_path, tmp_out, key, rec.get("aad"))
-        except Exception as e:  # wrong key / corrupted / binary missing
+            resolved = crypto_decrypt(enc_path, tmp_out, key, rec.get("aad"), byte_range)
+        except RangeNotSatisfiableError:
+            abort(416, "requested range not satisfiable")
+        except WrongPassphraseError:
+            abort(400, "decryption failed: wrong passphrase")
+        except CorruptedCiphertextError:
+            abort(422, "decryption failed: encrypted data is corrupted")
+        except Exception as e:  # legacy blob with bad key / binary missing
             abort(400, f"decryption failed (bad key or corrupted data): {type(e).__name__}")
 
         # Attempt to stream file defensively: load into memory to avoid race
@@ -250,12 +294,19 @@ def download_file(file_id: str):  # type: ignore
             except OSError:
                 pass
 
-        return send_file(
+        name, _ = _display_fields(rec, key)
+        resp = send_file(
             io.BytesIO(data),
             as_attachment=not inline,
-            download_name=rec["original_name"],
+            download_name=name or f"{canonical_id}.bin",
             mimetype=None if not inline else "application/octet-stream",
         )
+        resp.headers["Accept-Ranges"] = "bytes"
+        if resolved:
+            start, end, total = resolved
+            resp.status_code = 206
+            resp.headers["Content-Range"] = f"bytes {start}-{end}/{total}"
+        return resp
     except Exception as e:  # unexpected
         # Log stack for diagnostics
         tb = traceback.format_exc(limit=6)
@@ -506,6 +557,18 @@ def verify_file(file_id: str):  # type: ignore
         "cid": rec.get("cid"),
         "sha256": rec.get("sha256"),
     }
+    # With the passphrase, also authenticate every tag and the plaintext digest.
+    key = request.args.get("key") or request.headers.get("X-File-Key")
+    if key and exists_local:
+        try:
+            crypto_verify(enc_path, key, rec.get("aad"), rec.get("sha256"))
+            result["integrity"] = "verified"
+        except WrongPassphraseError:
+            abort(400, "verification failed: wrong passphrase")
+        except CorruptedCiphertextError:
+            result["integrity"] = "corrupted"
+        except Exception as e:
+            abort(400, f"verification failed: {type(e).__name__}")
     return result
 
 
@@ -542,16 +605,10 @@ def share_file(file_id: str):  # type: ignore
     if not pub_pem:
         abort(400, "recipient has not registered a sharing public key")
 
-    public_key = _load_public_key(pub_pem)
-    encrypted_bytes = public_key.encrypt(
-        passphrase.encode("utf-8"),
-        padding.OAEP(
-            mgf=padding.MGF1(algorithm=hashes.SHA256()),
-            algorithm=hashes.SHA256(),
-            label=None,
-        ),
-    )
-    encrypted_b64 = base64.b64encode(encrypted_bytes).decode("utf-8")
+    try:
+        encrypted_b64 = crypto_wrap_key(passphrase, pub_pem)
+    except ValueError as exc:
+        abort(400, f"invalid recipient public key: {exc}")
 
     now_ms = int(time.time() * 1000)
     expires_val: Optional[int] = None
@@ -561,15 +618,17 @@ def share_file(file_id: str):  # type: ignore
         except (TypeError, ValueError):
             abort(400, "expires_at must be an integer timestamp (ms)")
 
+    file_name, file_size = _display_fields(file_rec, passphrase)
     share_filter = {"file_id": canonical_id, "owner": owner, "recipient": recipient_addr}
     share_doc = {
         **share_filter,
         "encrypted_key": encrypted_b64,
         "note": note,
         "expires_at": expires_val,
-        "file_name": file_rec.get("original_name"),
-        "file_size": file_rec.get("size"),
+        "file_name": file_name,
+        "file_size": file_size,
         "sha256": file_rec.get("sha256"),
+        "aad": file_rec.get("aad"),
         "cid": file_rec.get("cid"),
         "gateway_url": ipfs_mod.gateway_url(file_rec.get("cid")) if file_rec.get("cid") else None,
     }
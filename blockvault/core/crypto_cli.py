@@ -1,14 +1,32 @@
 from __future__ import annotations
+import json
 import os
 import shutil
 import subprocess
+import tempfile
 import uuid
 from pathlib import Path
-from typing import Optional
+from typing import Optional, Tuple
 
 from flask import current_app
 
 
+class WrongPassphraseError(RuntimeError):
+    """The engine rejected the passphrase before touching the ciphertext."""
+
+
+class CorruptedCiphertextError(RuntimeError):
+    """The blob's header or ciphertext failed authentication."""
+
+
+class RangeNotSatisfiableError(RuntimeError):
+    """The requested byte range starts past the end of the plaintext."""
+
+
+class RangeUnsupportedError(RuntimeError):
+    """The blob cannot serve a byte range (it is compressed)."""
+
+
 def _resolve_binary() -> str:
     env_path = os.getenv("BLOCKVAULT_CRYPTO_BIN")
     if env_path and os.path.isfile(env_path) and os.access(env_path, os.X_OK):
@@ -36,7 +54,19 @@ def _resolve_binary() -> str:
     )
 
 
-def encrypt_file(plain_path: Path, encrypted_path: Path, key: str, aad: Optional[str]) -> None:
+def encrypt_file(
+    plain_path: Path,
+    encrypted_path: Path,
+    key: str,
+    aad: Optional[str],
+    pad: Optional[str] = None,
+    compress: Optional[str] = None,
+    name: Optional[str] = None,
+    media_type: Optional[str] = None,
+) -> None:
+    """Encrypt a file; ``pad`` ("padme", "block:<n>" or "none") hides its length and
+    ``compress`` ("zstd[:level]" or "none") compresses it first. ``name`` and
+    ``media_type`` go into the blob's encrypted metadata block."""
     bin_path = _resolve_binary()
     cmd = [
         bin_path,
@@ -50,12 +80,31 @@ def encrypt_file(plain_path: Path, encrypted_path: Path, key: str, aad: Optional
     ]
     if aad:
         cmd += ["--aad", aad]
+    if pad:
+        cmd += ["--pad", pad]
+    if compress:
+        cmd += ["--compress", compress]
+    if name:
+        cmd += ["--name", name]
+    if media_type:
+        cmd += ["--media-type", media_type]
     res = subprocess.run(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
     if res.returncode != 0:
         raise RuntimeError(f"Encryption failed: {res.stderr.decode()}")
 
 
-def decrypt_file(encrypted_path: Path, output_path: Path, key: str, aad: Optional[str]) -> None:
+def decrypt_file(
+    encrypted_path: Path,
+    output_path: Path,
+    key: str,
+    aad: Optional[str],
+    byte_range: Optional[str] = None,
+) -> Optional[Tuple[int, int, int]]:
+    """Decrypt a blob, or only plaintext bytes ``byte_range`` ("START-END" or "START-").
+
+    For a range, returns ``(start, end_inclusive, total_length)`` as resolved by the engine.
+    A compressed blob cannot serve ranges; it is decrypted whole and ``None`` is returned.
+    """
     bin_path = _resolve_binary()
     cmd = [
         bin_path,
@@ -69,9 +118,79 @@ def decrypt_file(encrypted_path: Path, output_path: Path, key: str, aad: Optiona
     ]
     if aad:
         cmd += ["--aad", aad]
+    if byte_range:
+        cmd += ["--range", byte_range]
+    res = subprocess.run(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+    if res.returncode != 0:
+        try:
+            _raise_decrypt_error(res.stderr.decode(), "Decryption")
+        except RangeUnsupportedError:
+            return decrypt_file(encrypted_path, output_path, key, aad)
+    if not byte_range:
+        return None
+    # "decrypted bytes START-END/TOTAL -> path"
+    span = res.stdout.decode().split()[2]
+    bounds, total = span.split("/")
+    start, end = bounds.split("-")
+    return int(start), int(end), int(total)
+
+
+def verify_file(encrypted_path: Path, key: str, aad: Optional[str], expect_sha256: Optional[str] = None) -> None:
+    """Authenticate a blob (and optionally its plaintext digest) without writing plaintext."""
+    bin_path = _resolve_binary()
+    cmd = [bin_path, "verify", "--input", str(encrypted_path), "--key", key]
+    if aad:
+        cmd += ["--aad", aad]
+    if expect_sha256:
+        cmd += ["--expect-sha256", expect_sha256]
+    res = subprocess.run(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+    if res.returncode != 0:
+        _raise_decrypt_error(res.stderr.decode(), "Verification")
+
+
+def _raise_decrypt_error(stderr: str, action: str) -> None:
+    if "beyond the end of the plaintext" in stderr:
+        raise RangeNotSatisfiableError(stderr.strip())
+    if "--range is not supported" in stderr:
+        raise RangeUnsupportedError(stderr.strip())
+    if "wrong passphrase" in stderr:
+        raise WrongPassphraseError(stderr.strip())
+    if "corrupted ciphertext" in stderr or "header tampered" in stderr or "sha256 mismatch" in stderr:
+        raise CorruptedCiphertextError(stderr.strip())
+    raise RuntimeError(f"{action} failed: {stderr}")
+
+
+def read_metadata(encrypted_path: Path, key: str) -> dict:
+    """Return the blob's encrypted metadata (name, media_type, size, mtime, tags) without decrypting it."""
+    bin_path = _resolve_binary()
+    cmd = [bin_path, "show-metadata", "--input", str(encrypted_path), "--key", key, "--json"]
     res = subprocess.run(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
     if res.returncode != 0:
-        raise RuntimeError(f"Decryption failed: {res.stderr.decode()}")
+        _raise_decrypt_error(res.stderr.decode(), "Metadata read")
+    return json.loads(res.stdout.decode())
+
+
+def wrap_key(passphrase: str, recipient_public_pem: str) -> str:
+    """Wrap ``passphrase`` to an RSA public key (PEM text) with RSA-OAEP-SHA256.
+
+    Returns the bare base64 share body stored as a share's ``encrypted_key``;
+    raises ``ValueError`` if the engine rejects the public key.
+    """
+    bin_path = _resolve_binary()
+    with tempfile.NamedTemporaryFile("w", suffix=".pem", delete=False) as fh:
+        fh.write(recipient_public_pem)
+        pem_path = fh.name
+    try:
+        cmd = [bin_path, "wrap-key", "--key", passphrase, "--recipient-pem", pem_path, "--bare"]
+        res = subprocess.run(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+    finally:
+        os.unlink(pem_path)
+    if res.returncode != 0:
+        stderr = res.stderr.decode()
+        if "is not a PEM RSA public key" in stderr:
+            raise ValueError("not a PEM RSA public key")
+        raise RuntimeError(f"Key wrapping failed: {stderr}")
+    return res.stdout.decode().strip()
 
 
 def ensure_storage_dir() -> Path:
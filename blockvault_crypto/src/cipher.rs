@@ -0,0 +1,176 @@
+//! AEAD ciphers selectable per file.
+//!
+//! All suites use 256-bit keys and 128-bit tags, so the segment layout is the
+//! same for every cipher; only the nonce width differs. XChaCha20-Poly1305 is
+//! fast without AES-NI and its 192-bit nonce leaves ample room for random
+//! prefixes under heavy passphrase reuse. AES-256-GCM-SIV is nonce-misuse
+//! resistant: a repeated (key, nonce) pair only reveals whether two segments
+//! are identical, instead of leaking their XOR as plain GCM would.
+//!
+//! None of these AEADs is key-committing on its own, so BVENC002 headers also
+//! carry [`key_commitment`]: a wrong passphrase is rejected before any
+//! ciphertext is read, and one blob cannot be crafted to open to different
+//! plaintexts under different keys (the "invisible salamander" attack).
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use anyhow::{anyhow, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::stream::NONCE_SUFFIX_LEN;
+
+pub const TAG_LEN: usize = 16;
+const COMMITMENT_LABEL: &[u8] = b"blockvault key commitment v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CipherId {
+    #[default]
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm = 1,
+    #[value(name = "xchacha20poly1305")]
+    XChaCha20Poly1305 = 2,
+    #[value(name = "aes-256-gcm-siv")]
+    Aes256GcmSiv = 3,
+}
+
+impl CipherId {
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(CipherId::Aes256Gcm),
+            2 => Ok(CipherId::XChaCha20Poly1305),
+            3 => Ok(CipherId::Aes256GcmSiv),
+            other => Err(anyhow!("unsupported cipher id {other}")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CipherId::Aes256Gcm => "aes-256-gcm",
+            CipherId::XChaCha20Poly1305 => "xchacha20poly1305",
+            CipherId::Aes256GcmSiv => "aes-256-gcm-siv",
+        }
+    }
+
+    pub fn nonce_len(&self) -> usize {
+        match self {
+            CipherId::Aes256Gcm | CipherId::Aes256GcmSiv => 12,
+            CipherId::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Random per-file part of the segment nonce.
+    pub fn nonce_prefix_len(&self) -> usize {
+        self.nonce_len() - NONCE_SUFFIX_LEN
+    }
+}
+
+fn commitment_mac(key: &[u8; 32]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(COMMITMENT_LABEL);
+    mac
+}
+
+/// Commitment to a file key: HMAC-SHA256(key, label). Finding two keys with
+/// the same commitment is as hard as finding a SHA-256 collision.
+pub fn key_commitment(key: &[u8; 32]) -> [u8; 32] {
+    commitment_mac(key).finalize().into_bytes().into()
+}
+
+/// Constant-time check of a stored [`key_commitment`].
+pub fn verify_key_commitment(key: &[u8; 32], commitment: &[u8]) -> bool {
+    commitment_mac(key).verify_slice(commitment).is_ok()
+}
+
+/// A keyed instance of one of the [`CipherId`] suites.
+pub enum SegmentCipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    Aes256GcmSiv(Box<Aes256GcmSiv>),
+}
+
+impl SegmentCipher {
+    pub fn new(id: CipherId, key: &[u8; 32]) -> Self {
+        match id {
+            CipherId::Aes256Gcm => SegmentCipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+            CipherId::XChaCha20Poly1305 => SegmentCipher::XChaCha20Poly1305(XChaCha20Poly1305::new(key.into())),
+            CipherId::Aes256GcmSiv => SegmentCipher::Aes256GcmSiv(Box::new(Aes256GcmSiv::new(key.into()))),
+        }
+    }
+
+    /// Seals `buf` in place, appending the tag. `nonce` must be `nonce_len()` bytes.
+    pub fn encrypt_in_place(&self, nonce: &[u8], aad: &[u8], buf: &mut Vec<u8>) -> aes_gcm::aead::Result<()> {
+        match self {
+            SegmentCipher::Aes256Gcm(c) => c.encrypt_in_place(nonce.into(), aad, buf),
+            SegmentCipher::XChaCha20Poly1305(c) => c.encrypt_in_place(nonce.into(), aad, buf),
+            SegmentCipher::Aes256GcmSiv(c) => c.encrypt_in_place(nonce.into(), aad, buf),
+        }
+    }
+
+    /// Verifies and strips the tag from `buf`, decrypting it in place.
+    pub fn decrypt_in_place(&self, nonce: &[u8], aad: &[u8], buf: &mut Vec<u8>) -> aes_gcm::aead::Result<()> {
+        match self {
+            SegmentCipher::Aes256Gcm(c) => c.decrypt_in_place(nonce.into(), aad, buf),
+            SegmentCipher::XChaCha20Poly1305(c) => c.decrypt_in_place(nonce.into(), aad, buf),
+            SegmentCipher::Aes256GcmSiv(c) => c.decrypt_in_place(nonce.into(), aad, buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: [u8; 12] = [3; 12];
+
+    fn seal(id: CipherId, msg: &[u8]) -> Vec<u8> {
+        let mut buf = msg.to_vec();
+        SegmentCipher::new(id, &[9; 32]).encrypt_in_place(&NONCE, b"", &mut buf).unwrap();
+        buf.truncate(msg.len());
+        buf
+    }
+
+    fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+        a.iter().zip(b).map(|(x, y)| x ^ y).collect()
+    }
+
+    #[test]
+    fn gcm_siv_nonce_reuse_leaks_no_keystream() {
+        let p1 = b"transfer $100 to account 111111";
+        let p2 = b"transfer $900 to account 999999";
+
+        // Plain GCM under a repeated nonce: c1 ^ c2 == p1 ^ p2.
+        let (g1, g2) = (seal(CipherId::Aes256Gcm, p1), seal(CipherId::Aes256Gcm, p2));
+        assert_eq!(xor(&g1, &g2), xor(p1, p2));
+
+        // GCM-SIV derives its keystream from the message, so it does not.
+        let (s1, s2) = (seal(CipherId::Aes256GcmSiv, p1), seal(CipherId::Aes256GcmSiv, p2));
+        assert_ne!(xor(&s1, &s2), xor(p1, p2));
+
+        // The only thing revealed is equality of repeated plaintexts.
+        assert_eq!(seal(CipherId::Aes256GcmSiv, p1), s1);
+    }
+
+    #[test]
+    fn key_commitment_distinguishes_keys() {
+        let commitment = key_commitment(&[1; 32]);
+        assert!(verify_key_commitment(&[1; 32], &commitment));
+        assert!(!verify_key_commitment(&[2; 32], &commitment));
+        assert!(!verify_key_commitment(&[1; 32], &commitment[..31]));
+    }
+
+    #[test]
+    fn gcm_siv_round_trips_and_authenticates() {
+        let cipher = SegmentCipher::new(CipherId::Aes256GcmSiv, &[9; 32]);
+        let mut buf = b"misuse resistant".to_vec();
+        cipher.encrypt_in_place(&NONCE, b"aad", &mut buf).unwrap();
+        assert_eq!(buf.len(), 16 + TAG_LEN);
+        let mut tampered = buf.clone();
+        tampered[0] ^= 1;
+        assert!(cipher.decrypt_in_place(&NONCE, b"aad", &mut tampered).is_err());
+        cipher.decrypt_in_place(&NONCE, b"aad", &mut buf).unwrap();
+        assert_eq!(buf, b"misuse resistant");
+    }
+}
@@ -0,0 +1,163 @@
+//! Optional compression of the plaintext before it is segmented.
+//!
+//! The codec is recorded in a critical header extension, so a reader that
+//! cannot decompress refuses the file instead of returning compressed bytes.
+//! Compression is off by default: the ciphertext length then depends on the
+//! content, which can leak information (as in CRIME/BREACH) when attacker
+//! controlled and secret data are compressed together.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+/// Default `--max-size` for decompressed output.
+pub const DEFAULT_MAX_DECOMPRESSED: u64 = 4 << 30;
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+const CODEC_ZSTD: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Zstandard at the given level.
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Value of the compression header extension. The level only affects
+    /// the encoder, so only the codec is recorded.
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => None,
+            Compression::Zstd(_) => Some(vec![CODEC_ZSTD]),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Zstd(_) => "zstd",
+        }
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self> {
+        match value {
+            [CODEC_ZSTD] => Ok(Compression::Zstd(ZSTD_DEFAULT_LEVEL)),
+            _ => Err(anyhow!("unsupported compression codec {}", hex::encode(value))),
+        }
+    }
+
+    /// Compresses all of `reader` into `writer`, returning the compressed length.
+    pub fn compress<R: Read, W: Write>(&self, reader: &mut R, writer: W) -> io::Result<u64> {
+        let mut counted = CountingWriter { inner: writer, count: 0 };
+        match *self {
+            Compression::None => {
+                io::copy(reader, &mut counted)?;
+            }
+            Compression::Zstd(level) => {
+                let mut encoder = zstd::stream::Encoder::new(&mut counted, level)?;
+                io::copy(reader, &mut encoder)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(counted.count)
+    }
+
+    /// Streams the decompression of `reader` into `writer`, failing once the
+    /// output would exceed `max_size` bytes.
+    pub fn decompress<R: Read, W: Write>(&self, reader: R, mut writer: W, max_size: u64) -> Result<u64> {
+        let mut decoder: Box<dyn Read> = match self {
+            Compression::None => Box::new(reader),
+            Compression::Zstd(_) => Box::new(zstd::stream::Decoder::new(reader)?.single_frame()),
+        };
+        let copied = io::copy(&mut (&mut decoder).take(max_size.saturating_add(1)), &mut writer)?;
+        if copied > max_size {
+            return Err(anyhow!(
+                "decompressed size exceeds limit of {max_size} bytes (possible decompression bomb; raise --max-size to allow it)"
+            ));
+        }
+        Ok(copied)
+    }
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let range = zstd::compression_level_range();
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd(ZSTD_DEFAULT_LEVEL)),
+            _ => match s.strip_prefix("zstd:").map(str::parse::<i32>) {
+                Some(Ok(level)) if range.contains(&level) => Ok(Compression::Zstd(level)),
+                Some(_) => Err(format!(
+                    "invalid compression '{s}': zstd level must be between {} and {}",
+                    range.start(),
+                    range.end()
+                )),
+                None => Err(format!("invalid compression '{s}': expected zstd, zstd:<level> or none")),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => f.write_str("none"),
+            Compression::Zstd(level) => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zstd_round_trips_and_enforces_size_cap() {
+        let data = b"legal text ".repeat(10_000);
+        let zstd: Compression = "zstd:5".parse().unwrap();
+        let mut compressed = Vec::new();
+        let len = zstd.compress(&mut &data[..], &mut compressed).unwrap();
+        assert_eq!(len, compressed.len() as u64);
+        assert!(compressed.len() < data.len() / 20);
+
+        let codec = Compression::decode(&zstd.encode().unwrap()).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(codec.decompress(&compressed[..], &mut out, data.len() as u64).unwrap(), data.len() as u64);
+        assert_eq!(out, data);
+
+        let err = codec.decompress(&compressed[..], io::sink(), 1000).unwrap_err();
+        assert!(err.to_string().contains("exceeds limit of 1000 bytes"), "{err}");
+    }
+
+    #[test]
+    fn parses_codec_names() {
+        assert_eq!("none".parse::<Compression>().unwrap().encode(), None);
+        assert_eq!("zstd".parse(), Ok(Compression::Zstd(3)));
+        assert_eq!("zstd:19".parse(), Ok(Compression::Zstd(19)));
+        assert!("zstd:99".parse::<Compression>().is_err());
+        assert!("gzip".parse::<Compression>().is_err());
+        assert!(Compression::decode(&[9]).is_err());
+    }
+}
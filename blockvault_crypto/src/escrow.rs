@@ -0,0 +1,100 @@
+//! Organization escrow key: when configured, every BlockVault file gets an
+//! extra key slot for it, labelled `escrow`, so the data key stays
+//! recoverable if a user forgets the passphrase.
+//!
+//! The key (an RSA or X25519 public key PEM) comes from `--escrow-pem` /
+//! `BLOCKVAULT_ESCROW_PEM` or, failing that, from the JSON config file named
+//! by `BLOCKVAULT_CONFIG`:
+//!
+//! ```text
+//! { "escrow_public_key": "/etc/blockvault/escrow.pem" }
+//! ```
+//!
+//! A relative path there is taken relative to the config file.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::slots::{load_recipient_pem, rsa_fingerprint, KeySlot, Recipient};
+
+pub const ESCROW_LABEL: &str = "escrow";
+const CONFIG_ENV: &str = "BLOCKVAULT_CONFIG";
+const CONFIG_KEY: &str = "escrow_public_key";
+
+/// The configured escrow key: `explicit` if given, else the config file's.
+pub fn configured(explicit: Option<&Path>) -> Result<Option<Recipient>> {
+    let path = match explicit {
+        Some(path) => Some(path.to_path_buf()),
+        None => match env::var_os(CONFIG_ENV) {
+            Some(config) => from_config(Path::new(&config))?,
+            None => None,
+        },
+    };
+    path.map(|path| load_recipient_pem(&path).map_err(|e| anyhow!("escrow key: {e}"))).transpose()
+}
+
+fn from_config(config: &Path) -> Result<Option<PathBuf>> {
+    let text = fs::read_to_string(config).map_err(|e| anyhow!("cannot read {}: {e}", config.display()))?;
+    let value: Value = serde_json::from_str(&text).map_err(|e| anyhow!("{} is not valid JSON: {e}", config.display()))?;
+    match value.get(CONFIG_KEY) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(path)) => Ok(Some(config.parent().unwrap_or(Path::new("")).join(path))),
+        Some(_) => Err(anyhow!("{}: {CONFIG_KEY} must be a path", config.display())),
+    }
+}
+
+/// Index of the escrow slot among `slots`, if any.
+pub fn find(slots: &[KeySlot]) -> Option<usize> {
+    slots.iter().position(|slot| slot.label == ESCROW_LABEL)
+}
+
+/// Whether a slot as `KeySlot::describe` shows it wraps to `key`; the label
+/// alone can be set by anyone who can add a slot.
+pub fn wraps_to(described: &Value, key: &Recipient) -> bool {
+    match key {
+        Recipient::Rsa(key) => rsa_fingerprint(key).is_ok_and(|f| described["fingerprint"] == hex::encode(f)),
+        Recipient::X25519(key) => described["public_key"] == hex::encode(key.as_bytes()),
+    }
+}
+
+/// Refuses a user-chosen slot label that would pass for the escrow slot.
+pub fn check_label(label: &str) -> Result<()> {
+    if label == ESCROW_LABEL {
+        return Err(anyhow!("the key slot label \"{ESCROW_LABEL}\" is reserved for the escrow key; pick another --label"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    #[test]
+    fn reads_the_key_from_the_config_file_and_matches_its_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = StaticSecret::random_from_rng(rand::thread_rng());
+        let public = PublicKey::from(&secret);
+        fs::write(dir.path().join("escrow.pem"), crate::hpke::public_key_pem(&public)).unwrap();
+        let config = dir.path().join("config.json");
+        fs::write(&config, r#"{ "escrow_public_key": "escrow.pem" }"#).unwrap();
+        assert_eq!(from_config(&config).unwrap(), Some(dir.path().join("escrow.pem")));
+        fs::write(&config, "{}").unwrap();
+        assert_eq!(from_config(&config).unwrap(), None);
+
+        let key = configured(Some(&dir.path().join("escrow.pem"))).unwrap().unwrap();
+        let slots = [
+            KeySlot::passphrase("passphrase", "pw", crate::kdf::KdfParams::Scrypt { log_n: 4, r: 8, p: 1 }, &[1; 32]).unwrap(),
+            KeySlot::for_recipient(ESCROW_LABEL, &key, &[1; 32]).unwrap(),
+        ];
+        assert_eq!(find(&slots), Some(1));
+        assert!(wraps_to(&slots[1].describe(), &key));
+        let other = Recipient::X25519(PublicKey::from(&StaticSecret::random_from_rng(rand::thread_rng())));
+        assert!(!wraps_to(&slots[1].describe(), &other));
+        assert!(check_label("escrow").is_err());
+    }
+}
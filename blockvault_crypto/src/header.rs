@@ -0,0 +1,274 @@
+//! Self-describing BVENC002 header.
+//!
+//! Layout (integers little-endian):
+//!
+//! ```text
+//! magic        8   "BVENC002"
+//! header_len   u32 length of everything below
+//! cipher       u8
+//! kdf          u8  0 when the key comes from key slots (see `slots`)
+//! kdf_params   u8 length + bytes
+//! salt         u8 length + bytes
+//! nonce_prefix u8 length + bytes
+//! segment_size u32
+//! extensions   { type u16, length u32, value }* until header_len is consumed
+//! ```
+//!
+//! Extension types with the high bit set are critical: a reader that does
+//! not understand one must refuse the file. All others may be skipped.
+//!
+//! The header is followed by a 16-byte header tag and is bound into the
+//! associated data of every segment (see `stream`), so no header byte can be
+//! changed without detection.
+
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+
+use crate::cipher::CipherId;
+use crate::kdf::KdfParams;
+use crate::slots::{MAX_SLOT_AREA, MIN_SLOT_AREA};
+use crate::stream::MAX_SEGMENT_SIZE;
+
+pub const MAGIC_V2: &[u8; 8] = b"BVENC002";
+pub const EXT_CRITICAL: u16 = 0x8000;
+/// 32-byte key commitment (see `cipher::key_commitment`).
+pub const EXT_KEY_COMMITMENT: u16 = 0x0001;
+/// True plaintext length (u64 LE) of a padded file, sealed with
+/// `stream::seal_header_field`.
+pub const EXT_PADDING: u16 = EXT_CRITICAL | 0x0002;
+/// Codec applied to the plaintext before encryption (see `compress`).
+pub const EXT_COMPRESSION: u16 = EXT_CRITICAL | 0x0003;
+/// Encrypted file metadata (see `metadata`).
+pub const EXT_METADATA: u16 = 0x0004;
+/// Size (u32 LE) of each of the two slot-area copies that follow the header tag.
+pub const EXT_KEY_SLOTS: u16 = EXT_CRITICAL | 0x0005;
+
+/// Human-readable name of a known extension type.
+pub fn extension_name(kind: u16) -> Option<&'static str> {
+    match kind {
+        EXT_KEY_COMMITMENT => Some("key-commitment"),
+        EXT_PADDING => Some("padding"),
+        EXT_COMPRESSION => Some("compression"),
+        EXT_METADATA => Some("metadata"),
+        EXT_KEY_SLOTS => Some("key-slots"),
+        _ => None,
+    }
+}
+const MAX_HEADER_LEN: u32 = 1 << 20;
+const KDF_KEY_SLOTS: u8 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub kind: u16,
+    pub value: Vec<u8>,
+}
+
+impl Extension {
+    pub fn is_critical(&self) -> bool {
+        self.kind & EXT_CRITICAL != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub cipher: CipherId,
+    /// Passphrase KDF for files keyed directly by a passphrase; `None` when
+    /// the key is wrapped in key slots instead.
+    pub kdf: Option<KdfParams>,
+    pub salt: Vec<u8>,
+    pub nonce_prefix: Vec<u8>,
+    pub segment_size: u32,
+    pub extensions: Vec<Extension>,
+}
+
+/// Bounds-checked reader over the header body.
+struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(anyhow!("header truncated while reading {what}"));
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2, what)?.try_into()?))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4, what)?.try_into()?))
+    }
+
+    fn short_bytes(&mut self, what: &str) -> Result<&'a [u8]> {
+        let len = self.u8(what)? as usize;
+        self.take(len, what)
+    }
+}
+
+fn put_short_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push(u8::try_from(bytes.len()).expect("header field longer than 255 bytes"));
+    out.extend_from_slice(bytes);
+}
+
+impl Header {
+    /// Looks up the first extension of the given type.
+    pub fn extension(&self, kind: u16) -> Option<&[u8]> {
+        self.extensions.iter().find(|e| e.kind == kind).map(|e| e.value.as_slice())
+    }
+
+    /// Serializes the header, including the magic and length prefix.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.push(self.cipher as u8);
+        body.push(self.kdf.map_or(KDF_KEY_SLOTS, |k| k.id()));
+        put_short_bytes(&mut body, &self.kdf.map(|k| k.encode()).unwrap_or_default());
+        put_short_bytes(&mut body, &self.salt);
+        put_short_bytes(&mut body, &self.nonce_prefix);
+        body.extend_from_slice(&self.segment_size.to_le_bytes());
+        for ext in &self.extensions {
+            body.extend_from_slice(&ext.kind.to_le_bytes());
+            body.extend_from_slice(&(ext.value.len() as u32).to_le_bytes());
+            body.extend_from_slice(&ext.value);
+        }
+
+        let mut out = Vec::with_capacity(MAGIC_V2.len() + 4 + body.len());
+        out.extend_from_slice(MAGIC_V2);
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    /// Reads the header that follows an already-consumed `BVENC002` magic.
+    pub fn read_after_magic<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut len = [0u8; 4];
+        reader
+            .read_exact(&mut len)
+            .map_err(|_| anyhow!("header truncated while reading header length"))?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_HEADER_LEN {
+            return Err(anyhow!("header length {len} exceeds limit of {MAX_HEADER_LEN} bytes"));
+        }
+        let mut body = vec![0u8; len as usize];
+        reader
+            .read_exact(&mut body)
+            .map_err(|_| anyhow!("header truncated: expected {len} bytes"))?;
+        Self::parse_body(&body)
+    }
+
+    fn parse_body(body: &[u8]) -> Result<Self> {
+        let mut f = Fields { buf: body };
+        let cipher = CipherId::from_id(f.u8("cipher id")?)?;
+        let kdf_id = f.u8("kdf id")?;
+        let kdf_params = f.short_bytes("kdf parameters")?;
+        let kdf = match kdf_id {
+            KDF_KEY_SLOTS if kdf_params.is_empty() => None,
+            KDF_KEY_SLOTS => return Err(anyhow!("key-slot files take no kdf parameters")),
+            id => Some(KdfParams::decode(id, kdf_params)?),
+        };
+        let salt = f.short_bytes("salt")?.to_vec();
+        let nonce_prefix = f.short_bytes("nonce prefix")?.to_vec();
+        if nonce_prefix.len() != cipher.nonce_prefix_len() {
+            return Err(anyhow!(
+                "nonce prefix is {} bytes, {} requires {}",
+                nonce_prefix.len(),
+                cipher.name(),
+                cipher.nonce_prefix_len()
+            ));
+        }
+        let segment_size = f.u32("segment size")?;
+        if segment_size == 0 || segment_size > MAX_SEGMENT_SIZE {
+            return Err(anyhow!("invalid segment size {segment_size}"));
+        }
+
+        let mut extensions = Vec::new();
+        while !f.buf.is_empty() {
+            let kind = f.u16("extension type")?;
+            let len = f.u32("extension length")? as usize;
+            let value = f.take(len, "extension value")?.to_vec();
+            extensions.push(Extension { kind, value });
+        }
+
+        Ok(Header { cipher, kdf, salt, nonce_prefix, segment_size, extensions })
+    }
+
+    /// Size of each slot-area copy, for files keyed through key slots.
+    pub fn slot_area_len(&self) -> Result<Option<u32>> {
+        let Some(value) = self.extension(EXT_KEY_SLOTS) else {
+            return Ok(None);
+        };
+        let len = u32::from_le_bytes(value.try_into().map_err(|_| anyhow!("key-slots extension must be 4 bytes"))?);
+        if !(MIN_SLOT_AREA..=MAX_SLOT_AREA).contains(&len) {
+            return Err(anyhow!("key slot area of {len} bytes out of range"));
+        }
+        Ok(Some(len))
+    }
+
+    /// Fails if the header carries a critical extension outside `known`.
+    pub fn check_critical(&self, known: &[u16]) -> Result<()> {
+        match self.extensions.iter().find(|e| e.is_critical() && !known.contains(&e.kind)) {
+            Some(ext) => Err(anyhow!("unsupported critical header extension 0x{:04x}", ext.kind)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Header {
+        Header {
+            cipher: CipherId::Aes256Gcm,
+            kdf: Some(KdfParams::Pbkdf2Sha256 { iterations: 1_000 }),
+            salt: vec![1; 16],
+            nonce_prefix: vec![2; CipherId::Aes256Gcm.nonce_prefix_len()],
+            segment_size: 4096,
+            extensions: vec![Extension { kind: 0x0042, value: b"skip me".to_vec() }],
+        }
+    }
+
+    fn reparse(bytes: &[u8]) -> Result<Header> {
+        assert_eq!(&bytes[..8], MAGIC_V2);
+        Header::read_after_magic(&mut &bytes[8..])
+    }
+
+    #[test]
+    fn round_trips_and_skips_unknown_extensions() {
+        let header = sample();
+        let parsed = reparse(&header.to_bytes()).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.extension(0x0042), Some(&b"skip me"[..]));
+        parsed.check_critical(&[]).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_critical_extension() {
+        let mut header = sample();
+        header.extensions.push(Extension { kind: EXT_CRITICAL | 0x0042, value: vec![] });
+        let parsed = reparse(&header.to_bytes()).unwrap();
+        let err = parsed.check_critical(&[]).unwrap_err();
+        assert!(err.to_string().contains("0x8042"), "{err}");
+    }
+
+    #[test]
+    fn reports_truncated_and_unknown_fields() {
+        let bytes = sample().to_bytes();
+        let err = reparse(&bytes[..bytes.len() - 3]).unwrap_err();
+        assert!(err.to_string().contains("header truncated"), "{err}");
+
+        let mut bad_cipher = bytes.clone();
+        bad_cipher[12] = 0xee;
+        let err = reparse(&bad_cipher).unwrap_err();
+        assert!(err.to_string().contains("unsupported cipher id 238"), "{err}");
+    }
+}
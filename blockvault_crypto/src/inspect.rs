@@ -0,0 +1,277 @@
+//! `inspect`: describe a blob from its header alone, without the passphrase.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+
+use crate::cipher::TAG_LEN;
+use crate::compress::Compression;
+use crate::header::{extension_name, Header, EXT_COMPRESSION, EXT_PADDING, MAGIC_V2};
+use crate::escrow;
+use crate::slots::{Recipient, SlotArea};
+use crate::{MAGIC, NONCE_LEN, PBKDF2_ITERS, SALT_LEN};
+
+pub fn inspect(path: &Path) -> Result<Value> {
+    let mut file = fs::File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut magic = [0u8; 8];
+    if file_len < magic.len() as u64 {
+        return Err(anyhow!("file is {file_len} bytes, too short to hold a BlockVault magic (8 bytes)"));
+    }
+    file.read_exact(&mut magic)?;
+    match &magic {
+        MAGIC => inspect_v1(file, file_len),
+        MAGIC_V2 => inspect_v2(file, file_len),
+        m => Err(unknown_magic(m)),
+    }
+}
+
+/// Explains why `magic` is not one this build can read.
+pub fn unknown_magic(magic: &[u8]) -> anyhow::Error {
+    if magic.starts_with(b"BVENC") {
+        anyhow!(
+            "unsupported BlockVault format version '{}' (this build reads BVENC001 and BVENC002)",
+            String::from_utf8_lossy(magic)
+        )
+    } else if magic.starts_with(b"age-encr") {
+        anyhow!("this is an age file: only decrypt and verify read age files (no ranges, metadata or inspection)")
+    } else if magic.first() == Some(&0x30) {
+        anyhow!("this looks like a CMS (S/MIME) message: only decrypt reads CMS files")
+    } else {
+        anyhow!("not a BlockVault blob: magic {} does not match BVENC001 or BVENC002", hex::encode(magic))
+    }
+}
+
+fn inspect_v1(mut file: fs::File, file_len: u64) -> Result<Value> {
+    let fixed = (MAGIC.len() + SALT_LEN + NONCE_LEN + TAG_LEN) as u64;
+    if file_len < fixed {
+        return Err(anyhow!("BVENC001 blob truncated: {file_len} bytes, need at least {fixed}"));
+    }
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    file.read_exact(&mut salt)?;
+    file.read_exact(&mut nonce)?;
+    let ciphertext_len = file_len - (MAGIC.len() + SALT_LEN + NONCE_LEN) as u64;
+    Ok(json!({
+        "format": "BVENC001",
+        "version": 1,
+        "cipher": "aes-256-gcm",
+        "kdf": { "name": "pbkdf2-sha256", "iterations": PBKDF2_ITERS },
+        "salt": hex::encode(salt),
+        "nonce": hex::encode(nonce),
+        "ciphertext_length": ciphertext_len,
+        "plaintext_length": ciphertext_len - TAG_LEN as u64,
+    }))
+}
+
+fn inspect_v2(mut file: fs::File, file_len: u64) -> Result<Value> {
+    let header = Header::read_after_magic(&mut file).map_err(|e| anyhow!("corrupt BVENC002 header: {e}"))?;
+    let header_len = header.to_bytes().len() as u64;
+    if file_len < header_len + TAG_LEN as u64 {
+        return Err(anyhow!("BVENC002 blob truncated: header tag missing after {header_len}-byte header"));
+    }
+    let mut tag = [0u8; TAG_LEN];
+    file.read_exact(&mut tag)?;
+    let slot_area = header.slot_area_len().map_err(|e| anyhow!("corrupt BVENC002 header: {e}"))?;
+    let area_len = 2 * slot_area.unwrap_or(0) as u64;
+    if file_len < header_len + TAG_LEN as u64 + area_len {
+        return Err(anyhow!("BVENC002 blob truncated: key slot area missing after the header tag"));
+    }
+
+    let extensions: Vec<Value> = header
+        .extensions
+        .iter()
+        .map(|ext| {
+            json!({
+                "type": format!("0x{:04x}", ext.kind),
+                "name": extension_name(ext.kind).unwrap_or("unknown"),
+                "critical": ext.is_critical(),
+                "length": ext.value.len(),
+                "value": hex::encode(&ext.value),
+            })
+        })
+        .collect();
+
+    let ciphertext_len = file_len - header_len - TAG_LEN as u64 - area_len;
+    let segment_ct = header.segment_size as u64 + TAG_LEN as u64;
+    let segments = ciphertext_len.div_ceil(segment_ct);
+    let mut report = json!({
+        "format": "BVENC002",
+        "version": 2,
+        "cipher": header.cipher.name(),
+        "nonce_prefix": hex::encode(&header.nonce_prefix),
+        "segment_size": header.segment_size,
+        "extensions": extensions,
+        "header_length": header_len,
+        "header_tag": hex::encode(tag),
+        "ciphertext_length": ciphertext_len,
+        "segments": segments,
+    });
+    if let Some(kdf) = header.kdf {
+        // Files from before key slots: the passphrase KDF lives in the header.
+        let mut params = Map::new();
+        params.insert("name".into(), kdf.name().into());
+        for (name, value) in kdf.costs() {
+            params.insert(name.into(), value.into());
+        }
+        report["kdf"] = params.into();
+        report["salt"] = hex::encode(&header.salt).into();
+    }
+    if let Some(capacity) = slot_area {
+        let mut area = vec![0u8; 2 * capacity as usize];
+        file.read_exact(&mut area)?;
+        report["key_slots"] = describe_slots(&area, capacity);
+    }
+    if let Some(codec) = header.extension(EXT_COMPRESSION) {
+        report["compression"] = Compression::decode(codec).map_or("unknown", |c| c.name()).into();
+    }
+    let last_segment = ciphertext_len - segments.saturating_sub(1) * segment_ct;
+    if segments == 0 || last_segment < TAG_LEN as u64 {
+        report["warning"] = "ciphertext truncated: final segment is missing or shorter than a tag".into();
+    } else {
+        // The true length of a padded file is sealed; only the padded one is visible.
+        let key = if header.extension(EXT_PADDING).is_some() { "padded_length" } else { "plaintext_length" };
+        report[key] = (ciphertext_len - segments * TAG_LEN as u64).into();
+    }
+    Ok(report)
+}
+
+/// The `key_slots` section of a report. Without the data key the slot area
+/// cannot be authenticated, so this shows the newest copy that parses.
+fn describe_slots(area: &[u8], capacity: u32) -> Value {
+    let Some(newest) = SlotArea::newest_unverified(area) else {
+        return json!({ "area_size": capacity, "warning": "key slot area is corrupted" });
+    };
+    let slots: Vec<Value> = newest
+        .slots
+        .iter()
+        .enumerate()
+        .map(|(index, slot)| {
+            let mut value = json!({ "index": index });
+            if let (Value::Object(fields), Value::Object(described)) = (&mut value, slot.describe()) {
+                fields.extend(described);
+            }
+            value
+        })
+        .collect();
+    let escrow = match escrow::find(&newest.slots) {
+        Some(index) => json!({ "present": true, "slot": index }),
+        None => json!({ "present": false }),
+    };
+    json!({ "area_size": capacity, "generation": newest.generation, "slots": slots, "escrow": escrow })
+}
+
+/// Adds to `report` whether its escrow slot wraps to the configured `key`,
+/// and a warning when no slot at all does. Returns whether one does.
+pub fn check_escrow(report: &mut Value, key: &Recipient) -> bool {
+    let Some(slots) = report.get_mut("key_slots") else {
+        return false;
+    };
+    if let Some(index) = slots["escrow"]["slot"].as_u64() {
+        let matches = escrow::wraps_to(&slots["slots"][index as usize], key);
+        slots["escrow"]["matches_configured_key"] = matches.into();
+    }
+    let wrapped = slots["slots"].as_array().is_some_and(|all| all.iter().any(|slot| escrow::wraps_to(slot, key)));
+    if !wrapped && slots.get("escrow").is_some() {
+        slots["escrow"]["warning"] = "no key slot wraps to the configured escrow key".into();
+    }
+    wrapped
+}
+
+/// `slot list`: the key slots of `path`, read without any credential.
+pub fn key_slots(path: &Path) -> Result<Value> {
+    let mut report = inspect(path)?;
+    match report.get_mut("key_slots") {
+        Some(slots) => Ok(slots.take()),
+        None => Err(anyhow!("{} has no key slots (it was encrypted before they were introduced)", path.display())),
+    }
+}
+
+/// Renders an inspection report as indented `key: value` lines.
+pub fn render_text(report: &Value) -> String {
+    let mut out = String::new();
+    render(report, 0, &mut out);
+    out
+}
+
+fn render(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                match v {
+                    Value::Object(_) => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        render(v, indent + 1, out);
+                    }
+                    Value::Array(items) if items.is_empty() => out.push_str(&format!("{pad}{key}: (none)\n")),
+                    Value::Array(items) => {
+                        out.push_str(&format!("{pad}{key}:\n"));
+                        for item in items {
+                            // "- " marks the first line of each element.
+                            let mut lines = String::new();
+                            render(item, indent + 2, &mut lines);
+                            out.push_str(&format!("{pad}  - {}", lines.trim_start()));
+                        }
+                    }
+                    Value::String(s) => out.push_str(&format!("{pad}{key}: {s}\n")),
+                    other => out.push_str(&format!("{pad}{key}: {other}\n")),
+                }
+            }
+        }
+        Value::String(s) => out.push_str(&format!("{pad}{s}\n")),
+        other => out.push_str(&format!("{pad}{other}\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encrypt_file, EncryptOptions};
+    use crate::kdf::KdfParams;
+
+    #[test]
+    fn reports_v2_header_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.txt");
+        let enc = dir.path().join("enc.bin");
+        std::fs::write(&plain, b"inspect me").unwrap();
+        let opts = EncryptOptions { kdf: KdfParams::Scrypt { log_n: 4, r: 8, p: 1 }, ..Default::default() };
+        encrypt_file(&plain, &enc, Some("pw"), None, &opts).unwrap();
+
+        let report = inspect(&enc).unwrap();
+        assert_eq!(report["format"], "BVENC002");
+        assert_eq!(report["cipher"], "aes-256-gcm");
+        assert_eq!(report["key_slots"]["generation"], 1);
+        let slot = &report["key_slots"]["slots"][0];
+        assert_eq!((&slot["type"], &slot["kdf"], &slot["log_n"]), (&"passphrase".into(), &"scrypt".into(), &4.into()));
+        assert_eq!(report["extensions"][0]["name"], "key-commitment");
+        assert_eq!(report["extensions"][1]["name"], "key-slots");
+        assert_eq!(report["segments"], 1);
+        assert_eq!(report["plaintext_length"], 10);
+
+        let text = render_text(&report);
+        assert!(text.contains("cipher: aes-256-gcm\n"), "{text}");
+        assert!(text.contains("    - index: 0\n"), "{text}");
+    }
+
+    #[test]
+    fn gives_precise_diagnostics() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blob.bin");
+        let cases: [(&[u8], &str); 4] = [
+            (b"BVE", "too short"),
+            (b"BVENC009xxxxxxxx", "unsupported BlockVault format version 'BVENC009'"),
+            (b"%PDF-1.7 hello w", "not a BlockVault blob: magic 255044462d312e37"),
+            (b"BVENC002\x10\x00\x00\x00\x01", "corrupt BVENC002 header: header truncated"),
+        ];
+        for (bytes, expected) in cases {
+            std::fs::write(&path, bytes).unwrap();
+            let err = inspect(&path).unwrap_err().to_string();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+}
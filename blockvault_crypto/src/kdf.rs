@@ -0,0 +1,286 @@
+//! Passphrase key derivation, driven by the parameters recorded in a header.
+//!
+//! Argon2id is the default for new files; scrypt and PBKDF2-HMAC-SHA256 stay
+//! available for interoperability and for decrypting older blobs. Decoded
+//! parameters are bounded so that a hostile header cannot make `decrypt`
+//! allocate more than 4 GiB (the Argon2id and scrypt caps) or run unbounded
+//! iterations before failing.
+
+use anyhow::{anyhow, Result};
+use argon2::{Algorithm, Argon2, Version};
+use pbkdf2::pbkdf2_hmac_array;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+pub const DEFAULT_PBKDF2_ITERS: u32 = 120_000;
+const MAX_PBKDF2_ITERS: u32 = 50_000_000;
+
+// Argon2id defaults follow the OWASP recommendation (19 MiB, 2 passes, 1 lane).
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+pub const DEFAULT_ARGON2_TIME: u32 = 2;
+pub const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+const MAX_ARGON2_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_ARGON2_TIME: u32 = 64;
+const MAX_PARALLELISM: u32 = 64;
+
+// scrypt defaults: N = 2^17, r = 8, p = 1 (128 MiB).
+pub const DEFAULT_SCRYPT_LOG_N: u8 = 17;
+pub const SCRYPT_R: u32 = 8;
+const MAX_SCRYPT_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+
+/// KDF choices accepted by `encrypt --kdf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KdfKind {
+    Argon2id,
+    Scrypt,
+    Pbkdf2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfParams {
+    Pbkdf2Sha256 { iterations: u32 },
+    Argon2id { memory_kib: u32, time: u32, parallelism: u32 },
+    Scrypt { log_n: u8, r: u32, p: u32 },
+}
+
+impl KdfParams {
+    const PBKDF2_SHA256: u8 = 1;
+    const ARGON2ID: u8 = 2;
+    const SCRYPT: u8 = 3;
+
+    pub fn id(&self) -> u8 {
+        match self {
+            KdfParams::Pbkdf2Sha256 { .. } => Self::PBKDF2_SHA256,
+            KdfParams::Argon2id { .. } => Self::ARGON2ID,
+            KdfParams::Scrypt { .. } => Self::SCRYPT,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KdfParams::Pbkdf2Sha256 { .. } => "pbkdf2-sha256",
+            KdfParams::Argon2id { .. } => "argon2id",
+            KdfParams::Scrypt { .. } => "scrypt",
+        }
+    }
+
+    /// Named cost parameters, for display.
+    pub fn costs(&self) -> Vec<(&'static str, u64)> {
+        match *self {
+            KdfParams::Pbkdf2Sha256 { iterations } => vec![("iterations", iterations.into())],
+            KdfParams::Argon2id { memory_kib, time, parallelism } => vec![
+                ("memory_kib", memory_kib.into()),
+                ("time", time.into()),
+                ("parallelism", parallelism.into()),
+            ],
+            KdfParams::Scrypt { log_n, r, p } => vec![("log_n", log_n.into()), ("r", r.into()), ("p", p.into())],
+        }
+    }
+
+    /// Builds parameters from the `encrypt` cost flags, filling in defaults.
+    ///
+    /// `memory_kib` maps to Argon2 `m` and to scrypt `N = memory_kib` (with
+    /// r = 8 one unit of N costs 1 KiB); `time` maps to Argon2 passes or
+    /// PBKDF2 iterations; `parallelism` maps to Argon2 lanes or scrypt `p`.
+    pub fn from_costs(
+        kind: KdfKind,
+        memory_kib: Option<u32>,
+        time: Option<u32>,
+        parallelism: Option<u32>,
+    ) -> Result<Self> {
+        let params = match kind {
+            KdfKind::Argon2id => KdfParams::Argon2id {
+                memory_kib: memory_kib.unwrap_or(DEFAULT_ARGON2_MEMORY_KIB),
+                time: time.unwrap_or(DEFAULT_ARGON2_TIME),
+                parallelism: parallelism.unwrap_or(DEFAULT_ARGON2_PARALLELISM),
+            },
+            KdfKind::Scrypt => {
+                if time.is_some() {
+                    return Err(anyhow!("scrypt has no separate time cost; raise --kdf-memory instead"));
+                }
+                let log_n = match memory_kib {
+                    Some(0) => return Err(anyhow!("scrypt memory cost must be positive")),
+                    Some(kib) => kib.ilog2() as u8,
+                    None => DEFAULT_SCRYPT_LOG_N,
+                };
+                KdfParams::Scrypt { log_n, r: SCRYPT_R, p: parallelism.unwrap_or(1) }
+            }
+            KdfKind::Pbkdf2 => {
+                if memory_kib.is_some() || parallelism.is_some() {
+                    return Err(anyhow!("pbkdf2 only takes a time cost (--kdf-time iterations)"));
+                }
+                KdfParams::Pbkdf2Sha256 { iterations: time.unwrap_or(DEFAULT_PBKDF2_ITERS) }
+            }
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    fn validate(&self) -> Result<()> {
+        match *self {
+            KdfParams::Pbkdf2Sha256 { iterations } => {
+                if iterations == 0 || iterations > MAX_PBKDF2_ITERS {
+                    return Err(anyhow!("pbkdf2 iteration count {iterations} out of range"));
+                }
+            }
+            KdfParams::Argon2id { memory_kib, time, parallelism } => {
+                if parallelism == 0 || parallelism > MAX_PARALLELISM {
+                    return Err(anyhow!("argon2id parallelism {parallelism} out of range"));
+                }
+                if time == 0 || time > MAX_ARGON2_TIME {
+                    return Err(anyhow!("argon2id time cost {time} out of range"));
+                }
+                if memory_kib < 8 * parallelism || memory_kib > MAX_ARGON2_MEMORY_KIB {
+                    return Err(anyhow!("argon2id memory cost {memory_kib} KiB out of range"));
+                }
+            }
+            KdfParams::Scrypt { log_n, r, p } => {
+                if log_n == 0 || log_n >= 32 || r == 0 || p == 0 || p > MAX_PARALLELISM {
+                    return Err(anyhow!("scrypt parameters log_n={log_n} r={r} p={p} out of range"));
+                }
+                let memory = (128 * r as u64).checked_mul(1u64 << log_n).filter(|&m| m <= MAX_SCRYPT_MEMORY);
+                if memory.is_none() {
+                    return Err(anyhow!("scrypt parameters log_n={log_n} r={r} need more memory than the limit"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Serializes the parameters (without the id) for the header.
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            KdfParams::Pbkdf2Sha256 { iterations } => iterations.to_le_bytes().to_vec(),
+            KdfParams::Argon2id { memory_kib, time, parallelism } => {
+                [memory_kib.to_le_bytes(), time.to_le_bytes(), parallelism.to_le_bytes()].concat()
+            }
+            KdfParams::Scrypt { log_n, r, p } => {
+                let mut out = vec![log_n];
+                out.extend_from_slice(&r.to_le_bytes());
+                out.extend_from_slice(&p.to_le_bytes());
+                out
+            }
+        }
+    }
+
+    pub fn decode(id: u8, params: &[u8]) -> Result<Self> {
+        let word = |i: usize| u32::from_le_bytes(params[i..i + 4].try_into().unwrap());
+        let expect_len = |name: &str, len: usize| {
+            if params.len() == len {
+                Ok(())
+            } else {
+                Err(anyhow!("{name} parameters must be {len} bytes, got {}", params.len()))
+            }
+        };
+        let kdf = match id {
+            Self::PBKDF2_SHA256 => {
+                expect_len("pbkdf2", 4)?;
+                KdfParams::Pbkdf2Sha256 { iterations: word(0) }
+            }
+            Self::ARGON2ID => {
+                expect_len("argon2id", 12)?;
+                KdfParams::Argon2id { memory_kib: word(0), time: word(4), parallelism: word(8) }
+            }
+            Self::SCRYPT => {
+                expect_len("scrypt", 9)?;
+                KdfParams::Scrypt { log_n: params[0], r: word(1), p: word(5) }
+            }
+            other => return Err(anyhow!("unsupported kdf id {other}")),
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+
+    pub fn derive(&self, passphrase: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        match *self {
+            KdfParams::Pbkdf2Sha256 { iterations } => {
+                *key = pbkdf2_hmac_array::<Sha256, 32>(passphrase, salt, iterations);
+            }
+            KdfParams::Argon2id { memory_kib, time, parallelism } => {
+                let params = argon2::Params::new(memory_kib, time, parallelism, Some(32))
+                    .map_err(|e| anyhow!("invalid argon2id parameters: {e}"))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase, salt, key.as_mut())
+                    .map_err(|e| anyhow!("argon2id failed: {e}"))?;
+            }
+            KdfParams::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, 32)
+                    .map_err(|e| anyhow!("invalid scrypt parameters: {e}"))?;
+                scrypt::scrypt(passphrase, salt, &params, key.as_mut())
+                    .map_err(|e| anyhow!("scrypt failed: {e}"))?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams::Argon2id {
+            memory_kib: DEFAULT_ARGON2_MEMORY_KIB,
+            time: DEFAULT_ARGON2_TIME,
+            parallelism: DEFAULT_ARGON2_PARALLELISM,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_round_trip_and_reject_out_of_range() {
+        let kdf = KdfParams::Pbkdf2Sha256 { iterations: 1_000 };
+        assert_eq!(KdfParams::decode(kdf.id(), &kdf.encode()).unwrap(), kdf);
+        assert!(KdfParams::decode(kdf.id(), &0u32.to_le_bytes()).is_err());
+        assert!(KdfParams::decode(kdf.id(), &[1, 2, 3]).is_err());
+        assert!(KdfParams::decode(99, &[]).is_err());
+
+        for kdf in [
+            KdfParams::Argon2id { memory_kib: 64, time: 1, parallelism: 1 },
+            KdfParams::Scrypt { log_n: 4, r: 8, p: 1 },
+        ] {
+            assert_eq!(KdfParams::decode(kdf.id(), &kdf.encode()).unwrap(), kdf);
+        }
+        let hostile = KdfParams::Argon2id { memory_kib: u32::MAX, time: 1, parallelism: 1 };
+        assert!(KdfParams::decode(hostile.id(), &hostile.encode()).is_err());
+        let hostile = KdfParams::Scrypt { log_n: 30, r: 8, p: 1 };
+        assert!(KdfParams::decode(hostile.id(), &hostile.encode()).is_err());
+        // 128 * 2^26 * 2^31 wraps to zero in u64.
+        let hostile = KdfParams::Scrypt { log_n: 31, r: 1 << 26, p: 1 };
+        assert!(KdfParams::decode(hostile.id(), &hostile.encode()).is_err());
+    }
+
+    #[test]
+    fn maps_cost_flags_per_kdf() {
+        assert_eq!(
+            KdfParams::from_costs(KdfKind::Scrypt, Some(1024), None, Some(2)).unwrap(),
+            KdfParams::Scrypt { log_n: 10, r: SCRYPT_R, p: 2 }
+        );
+        assert_eq!(
+            KdfParams::from_costs(KdfKind::Pbkdf2, None, Some(5_000), None).unwrap(),
+            KdfParams::Pbkdf2Sha256 { iterations: 5_000 }
+        );
+        assert_eq!(KdfParams::from_costs(KdfKind::Argon2id, None, None, None).unwrap(), KdfParams::default());
+        assert!(KdfParams::from_costs(KdfKind::Scrypt, None, Some(3), None).is_err());
+        assert!(KdfParams::from_costs(KdfKind::Pbkdf2, Some(1024), None, None).is_err());
+    }
+
+    #[test]
+    fn kdfs_are_deterministic_and_distinct() {
+        let salt = [5u8; 16];
+        let kdfs = [
+            KdfParams::Pbkdf2Sha256 { iterations: 10 },
+            KdfParams::Argon2id { memory_kib: 64, time: 1, parallelism: 1 },
+            KdfParams::Scrypt { log_n: 4, r: 8, p: 1 },
+        ];
+        let keys: Vec<[u8; 32]> = kdfs.iter().map(|k| *k.derive(b"pw", &salt).unwrap()).collect();
+        for (kdf, key) in kdfs.iter().zip(&keys) {
+            assert_eq!(*kdf.derive(b"pw", &salt).unwrap(), *key);
+            assert_ne!(*kdf.derive(b"other", &salt).unwrap(), *key);
+        }
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(keys[1], keys[2]);
+    }
+}
@@ -0,0 +1,176 @@
+//! Master-key mode: one master secret per user, stretched once per session,
+//! from which each file's key is derived with HKDF-SHA256.
+//!
+//! `master init` writes a descriptor (JSON: KDF parameters, salt and master
+//! ID) for a passphrase; `master unlock` runs the KDF once and prints the
+//! master key for `BLOCKVAULT_MASTER_KEY`, after which encrypting and
+//! decrypting cost one HKDF per file. A file's key is
+//!
+//! ```text
+//! HKDF-SHA256(ikm = master key, salt = none,
+//!             info = "blockvault file key v1" || u8 len || context || file_id)
+//! ```
+//!
+//! and wraps the data key in an `hkdf-master` key slot, which records only
+//! the derivation path: master ID, random file ID and context label. Handing
+//! out one file's key (`master derive`) opens that file and nothing else.
+//!
+//! The master ID is the first 8 bytes of HKDF-Expand(master, "blockvault
+//! master id v1"): it tells masters apart and lets `master unlock` reject a
+//! wrong passphrase, without revealing the key.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::kdf::KdfParams;
+
+pub const MASTER_ID_LEN: usize = 8;
+pub const FILE_ID_LEN: usize = 16;
+pub const DEFAULT_CONTEXT: &str = "file";
+const ID_INFO: &[u8] = b"blockvault master id v1";
+const FILE_KEY_INFO: &[u8] = b"blockvault file key v1";
+const SALT_LEN: usize = 16;
+const DESCRIPTOR_VERSION: u64 = 1;
+
+/// A per-file key derived from a master key.
+pub type FileKey = Zeroizing<[u8; 32]>;
+
+/// The stretched master secret.
+#[derive(Clone)]
+pub struct MasterKey(Zeroizing<[u8; 32]>);
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MasterKey({})", hex::encode(self.id()))
+    }
+}
+
+impl MasterKey {
+    /// Parses the hex form `master unlock` prints.
+    pub fn from_hex(text: &str) -> Result<Self> {
+        Ok(MasterKey(parse_key(text, "master key")?))
+    }
+
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(hex::encode(self.0.as_slice()))
+    }
+
+    pub fn id(&self) -> [u8; MASTER_ID_LEN] {
+        let mut id = [0u8; MASTER_ID_LEN];
+        Hkdf::<Sha256>::from_prk(self.0.as_slice())
+            .expect("a 32-byte key is a valid PRK")
+            .expand(ID_INFO, &mut id)
+            .expect("8 bytes is a valid HKDF length");
+        id
+    }
+
+    /// Key of the file with `file_id`, under the `context` label.
+    pub fn file_key(&self, file_id: &[u8; FILE_ID_LEN], context: &str) -> FileKey {
+        let context_len = u8::try_from(context.len()).expect("context label longer than 255 bytes");
+        let info = [FILE_KEY_INFO, &[context_len], context.as_bytes(), file_id].concat();
+        let mut key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(None, self.0.as_slice())
+            .expand(&info, key.as_mut())
+            .expect("32 bytes is a valid HKDF length");
+        key
+    }
+}
+
+/// Parses a 256-bit key given as 64 hex digits.
+pub fn parse_key(text: &str, what: &str) -> Result<Zeroizing<[u8; 32]>> {
+    let bytes = Zeroizing::new(hex::decode(text.trim()).map_err(|_| anyhow!("{what} is not hex"))?);
+    let key = <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| anyhow!("{what} must be 32 bytes (64 hex digits)"))?;
+    Ok(Zeroizing::new(key))
+}
+
+/// Refuses a context label that does not fit a key slot.
+pub fn check_context(context: &str) -> Result<()> {
+    if context.is_empty() || context.len() > 255 {
+        return Err(anyhow!("--context must be 1 to 255 bytes"));
+    }
+    Ok(())
+}
+
+/// What `master init` writes: how to stretch the passphrase into the master key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterFile {
+    pub kdf: KdfParams,
+    pub salt: Vec<u8>,
+    pub id: [u8; MASTER_ID_LEN],
+}
+
+impl MasterFile {
+    /// A new master key for `passphrase`, with a fresh salt.
+    pub fn create(passphrase: &str, kdf: KdfParams) -> Result<(Self, MasterKey)> {
+        let mut salt = vec![0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let master = MasterKey(kdf.derive(passphrase.as_bytes(), &salt)?);
+        Ok((MasterFile { kdf, salt, id: master.id() }, master))
+    }
+
+    /// Runs the KDF: the one slow step of a session.
+    pub fn unlock(&self, passphrase: &str) -> Result<MasterKey> {
+        let master = MasterKey(self.kdf.derive(passphrase.as_bytes(), &self.salt)?);
+        if master.id() != self.id {
+            return Err(anyhow!("wrong passphrase"));
+        }
+        Ok(master)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": DESCRIPTOR_VERSION,
+            "master_id": hex::encode(self.id),
+            "kdf": self.kdf.name(),
+            "kdf_id": self.kdf.id(),
+            "kdf_params": hex::encode(self.kdf.encode()),
+            "salt": hex::encode(&self.salt),
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text).map_err(|e| anyhow!("master key file is not valid JSON: {e}"))?;
+        match value["version"].as_u64() {
+            Some(DESCRIPTOR_VERSION) => {}
+            Some(v) => return Err(anyhow!("unsupported master key file version {v} (this build reads {DESCRIPTOR_VERSION})")),
+            None => return Err(anyhow!("master key file has no version")),
+        }
+        let field = |name: &str| {
+            let text = value[name].as_str().ok_or_else(|| anyhow!("master key file has no {name}"))?;
+            hex::decode(text).map_err(|_| anyhow!("master key file: {name} is not hex"))
+        };
+        let kdf_id = value["kdf_id"].as_u64().and_then(|id| u8::try_from(id).ok());
+        let kdf_id = kdf_id.ok_or_else(|| anyhow!("master key file has no kdf_id"))?;
+        let id = field("master_id")?.try_into().map_err(|_| anyhow!("master key file: master_id must be 8 bytes"))?;
+        Ok(MasterFile { kdf: KdfParams::decode(kdf_id, &field("kdf_params")?)?, salt: field("salt")?, id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlocks_once_and_derives_distinct_file_keys() {
+        let kdf = KdfParams::Scrypt { log_n: 4, r: 8, p: 1 };
+        let (file, master) = MasterFile::create("hunter2", kdf).unwrap();
+        let parsed = MasterFile::parse(&file.to_json().to_string()).unwrap();
+        assert_eq!(parsed, file);
+        let unlocked = parsed.unlock("hunter2").unwrap();
+        assert_eq!(unlocked.to_hex(), master.to_hex());
+        assert_eq!(parsed.unlock("hunter3").unwrap_err().to_string(), "wrong passphrase");
+
+        let again = MasterKey::from_hex(&master.to_hex()).unwrap();
+        let (a, b) = ([1u8; FILE_ID_LEN], [2u8; FILE_ID_LEN]);
+        assert_eq!(again.file_key(&a, "file"), master.file_key(&a, "file"));
+        assert_ne!(master.file_key(&a, "file"), master.file_key(&b, "file"));
+        assert_ne!(master.file_key(&a, "file"), master.file_key(&a, "legal-hold"));
+        assert!(MasterKey::from_hex("abcd").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Encrypted file metadata carried in the BVENC002 header.
+//!
+//! The metadata is a small JSON object sealed with
+//! `stream::seal_header_field`, so it is confidential and authenticated but
+//! can be read with the passphrase alone, without touching the body. The
+//! extension is not critical: an older reader still decrypts the contents.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub media_type: Option<String>,
+    /// Plaintext size before compression and padding.
+    pub size: Option<u64>,
+    /// Modification time of the source file, in Unix seconds.
+    pub mtime: Option<u64>,
+    pub encrypted_at: Option<u64>,
+    pub tags: BTreeMap<String, String>,
+}
+
+fn unix_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+/// Parses a `--tag KEY=VALUE` argument.
+pub fn parse_tag(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid tag '{s}': expected KEY=VALUE")),
+    }
+}
+
+impl Metadata {
+    /// Describes `path`, taking the name from its last component unless
+    /// `name` overrides it.
+    pub fn for_file(path: &Path, name: Option<String>, media_type: Option<String>, tags: Vec<(String, String)>) -> Result<Self> {
+        let stat = fs::metadata(path)?;
+        Ok(Metadata {
+            name: name.or_else(|| path.file_name().map(|n| n.to_string_lossy().into_owned())),
+            media_type,
+            size: Some(stat.len()),
+            mtime: stat.modified().ok().and_then(unix_secs),
+            encrypted_at: unix_secs(SystemTime::now()),
+            tags: tags.into_iter().collect(),
+        })
+    }
+
+    /// Describes plaintext that arrives without a source file, such as the
+    /// literal data of an OpenPGP message; `mtime` is in Unix seconds.
+    pub fn for_import(name: Option<String>, size: u64, mtime: Option<u64>) -> Self {
+        Metadata {
+            name,
+            size: Some(size),
+            mtime,
+            encrypted_at: unix_secs(SystemTime::now()),
+            ..Metadata::default()
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        let fields = [
+            ("name", self.name.clone().map(Value::from)),
+            ("media_type", self.media_type.clone().map(Value::from)),
+            ("size", self.size.map(Value::from)),
+            ("mtime", self.mtime.map(Value::from)),
+            ("encrypted_at", self.encrypted_at.map(Value::from)),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                map.insert(key.into(), value);
+            }
+        }
+        map.insert("tags".into(), json!(self.tags));
+        Value::Object(map)
+    }
+
+    /// Serializes to JSON, space-padded to a multiple of `block` bytes so that
+    /// the sealed size does not reveal the exact length of the name or tags.
+    pub fn encode(&self, block: usize) -> Vec<u8> {
+        let mut bytes = self.to_json().to_string().into_bytes();
+        let padded = bytes.len().div_ceil(block.max(1)) * block.max(1);
+        bytes.resize(padded, b' ');
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let value: Value = serde_json::from_slice(bytes).map_err(|e| anyhow!("invalid metadata: {e}"))?;
+        let obj = value.as_object().ok_or_else(|| anyhow!("invalid metadata: expected a JSON object"))?;
+        let text = |key: &str| obj.get(key).and_then(Value::as_str).map(str::to_string);
+        let number = |key: &str| obj.get(key).and_then(Value::as_u64);
+        let tags = match obj.get("tags") {
+            Some(Value::Object(tags)) => tags
+                .iter()
+                .map(|(k, v)| match v {
+                    Value::String(v) => Ok((k.clone(), v.clone())),
+                    _ => Err(anyhow!("invalid metadata: tag '{k}' is not a string")),
+                })
+                .collect::<Result<_>>()?,
+            _ => BTreeMap::new(),
+        };
+        Ok(Metadata {
+            name: text("name"),
+            media_type: text("media_type"),
+            size: number("size"),
+            mtime: number("mtime"),
+            encrypted_at: number("encrypted_at"),
+            tags,
+        })
+    }
+
+    /// The recorded name reduced to a single path component, so restoring it
+    /// can never write outside the chosen directory.
+    pub fn safe_file_name(&self) -> Result<&str> {
+        let name = self.name.as_deref().ok_or_else(|| anyhow!("metadata has no file name to restore"))?;
+        match Path::new(name).file_name().and_then(|n| n.to_str()) {
+            Some(base) if base == name => Ok(base),
+            _ => Err(anyhow!("refusing to restore unsafe file name {name:?}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_padded_json() {
+        let metadata = Metadata {
+            name: Some("contract.pdf".into()),
+            media_type: Some("application/pdf".into()),
+            size: Some(1234),
+            mtime: Some(1_700_000_000),
+            encrypted_at: None,
+            tags: [("matter".to_string(), "42".to_string())].into(),
+        };
+        let bytes = metadata.encode(256);
+        assert_eq!(bytes.len(), 256);
+        assert_eq!(Metadata::decode(&bytes).unwrap(), metadata);
+        assert!(Metadata::decode(b"[1]").is_err());
+        assert_eq!(parse_tag("a=b=c").unwrap(), ("a".to_string(), "b=c".to_string()));
+        assert!(parse_tag("=x").is_err());
+    }
+
+    #[test]
+    fn restores_only_plain_file_names() {
+        let with = |name: &str| Metadata { name: Some(name.into()), ..Default::default() };
+        assert_eq!(with("report.txt").safe_file_name().unwrap(), "report.txt");
+        for bad in ["../etc/passwd", "/etc/passwd", "a/b", "..", ""] {
+            assert!(with(bad).safe_file_name().is_err(), "{bad}");
+        }
+    }
+}
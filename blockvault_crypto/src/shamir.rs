@@ -0,0 +1,371 @@
+//! Shamir secret sharing over GF(256) of a passphrase or a file's data key,
+//! so that any `threshold` of `count` custodians can recover it and fewer
+//! learn nothing about it.
+//!
+//! Each byte of the secret is the constant term of its own random polynomial
+//! of degree `threshold - 1`; share `x` (1..=255) holds the polynomials'
+//! values at `x`. Arithmetic uses the AES field polynomial (x^8 + x^4 + x^3 +
+//! x + 1) and is branch-free, without lookup tables.
+//!
+//! A share is armored like other shares (see `share`), with `Algorithm:
+//! SHAMIR-GF256` and the body (integers big-endian):
+//!
+//! ```text
+//! split_id   16  random, the same in every share of one split
+//! kind        u8  0 = passphrase, 1 = data key
+//! threshold   u8
+//! count       u8
+//! index       u8  the share ID, x
+//! value       secret length
+//! checksum    4   first bytes of SHA-256 over everything before it
+//! ```
+//!
+//! A share may instead be wrapped to its custodian's key: `Algorithm:
+//! SHAMIR-GF256-RSA-OAEP-SHA256` holds the RSA-OAEP ciphertext of that body,
+//! `SHAMIR-GF256-HPKE-X25519-SHA256-CHACHA20POLY1305` the HPKE (Base mode)
+//! `enc` followed by the sealed body.
+//!
+//! The checksum catches damaged shares. Given more than `threshold` shares,
+//! `combine` also checks that the extra ones lie on the same polynomials, so
+//! a wrong or forged share is reported instead of yielding a wrong secret.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rand::RngCore;
+use rsa::{Oaep, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::hpke;
+use crate::share::{armor, dearmor, ARMOR_BEGIN, ARMOR_END};
+use crate::slots::{Credential, Recipient};
+
+const ALGORITHM: &str = "SHAMIR-GF256";
+const RSA_ALGORITHM: &str = "SHAMIR-GF256-RSA-OAEP-SHA256";
+const HPKE_ALGORITHM: &str = "SHAMIR-GF256-HPKE-X25519-SHA256-CHACHA20POLY1305";
+const HPKE_INFO: &[u8] = b"blockvault shamir share v1";
+const SPLIT_ID_LEN: usize = 16;
+const CHECKSUM_LEN: usize = 4;
+const FIXED_LEN: usize = SPLIT_ID_LEN + 4 + CHECKSUM_LEN;
+
+/// What a split protects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretKind {
+    Passphrase,
+    DataKey,
+}
+
+impl SecretKind {
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(SecretKind::Passphrase),
+            1 => Ok(SecretKind::DataKey),
+            other => Err(anyhow!("unsupported Shamir secret kind {other}")),
+        }
+    }
+}
+
+/// One custodian's share of a secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShamirShare {
+    pub split_id: [u8; SPLIT_ID_LEN],
+    pub kind: SecretKind,
+    pub threshold: u8,
+    pub count: u8,
+    /// The x coordinate, 1..=count; shown as the share ID.
+    pub index: u8,
+    pub value: Zeroizing<Vec<u8>>,
+}
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse as a^254; `a` must not be zero.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    for bit in 0..8 {
+        if (254u8 >> bit) & 1 == 1 {
+            result = gf_mul(result, power);
+        }
+        power = gf_mul(power, power);
+    }
+    result
+}
+
+/// Value at `x` of the polynomials through `shares` (which have distinct
+/// indices), by Lagrange interpolation.
+fn interpolate(shares: &[&ShamirShare], x: u8) -> Zeroizing<Vec<u8>> {
+    let mut out = Zeroizing::new(vec![0u8; shares[0].value.len()]);
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf_mul(basis, gf_mul(x ^ other.index, gf_inv(share.index ^ other.index)));
+            }
+        }
+        for (out, &y) in out.iter_mut().zip(share.value.iter()) {
+            *out ^= gf_mul(basis, y);
+        }
+    }
+    out
+}
+
+/// Splits `secret` into `count` shares, any `threshold` of which recover it.
+pub fn split(secret: &[u8], kind: SecretKind, threshold: u8, count: u8) -> Result<Vec<ShamirShare>> {
+    if threshold < 2 || threshold > count {
+        return Err(anyhow!("the threshold must be between 2 and the number of shares ({count}), got {threshold}"));
+    }
+    if secret.is_empty() {
+        return Err(anyhow!("nothing to split: the secret is empty"));
+    }
+    let mut rng = rand::thread_rng();
+    let mut split_id = [0u8; SPLIT_ID_LEN];
+    rng.fill_bytes(&mut split_id);
+    // coefficients[d][i] multiplies x^(d + 1) in the polynomial of byte i.
+    let coefficients = (1..threshold)
+        .map(|_| {
+            let mut row = Zeroizing::new(vec![0u8; secret.len()]);
+            rng.fill_bytes(&mut row);
+            row
+        })
+        .collect::<Vec<_>>();
+    Ok((1..=count)
+        .map(|x| {
+            let mut value = Zeroizing::new(vec![0u8; secret.len()]);
+            for (i, y) in value.iter_mut().enumerate() {
+                // Horner's rule, from the highest coefficient down.
+                *y = coefficients.iter().rev().fold(0, |acc, row| gf_mul(acc, x) ^ row[i]);
+                *y = gf_mul(*y, x) ^ secret[i];
+            }
+            ShamirShare { split_id, kind, threshold, count, index: x, value }
+        })
+        .collect())
+}
+
+/// Recovers the secret from at least `threshold` shares of one split,
+/// checking any extra shares against it.
+pub fn combine(shares: &[ShamirShare]) -> Result<(SecretKind, Zeroizing<Vec<u8>>)> {
+    let first = shares.first().ok_or_else(|| anyhow!("no shares given"))?;
+    let mut distinct: Vec<&ShamirShare> = Vec::new();
+    for share in shares {
+        if (share.split_id, share.kind, share.threshold, share.count) != (first.split_id, first.kind, first.threshold, first.count)
+            || share.value.len() != first.value.len()
+        {
+            return Err(anyhow!("share {} belongs to a different split than share {}", share.index, first.index));
+        }
+        match distinct.iter().find(|seen| seen.index == share.index) {
+            Some(seen) if seen.value == share.value => {}
+            Some(_) => return Err(anyhow!("two different shares claim ID {}", share.index)),
+            None => distinct.push(share),
+        }
+    }
+    let threshold = first.threshold as usize;
+    if distinct.len() < threshold {
+        return Err(anyhow!("{} of {} shares are needed, got {}", threshold, first.count, distinct.len()));
+    }
+    let (basis, extra) = distinct.split_at(threshold);
+    for share in extra {
+        if interpolate(basis, share.index) != share.value {
+            return Err(anyhow!("share {} does not match the others: one of the shares is wrong", share.index));
+        }
+    }
+    Ok((first.kind, interpolate(basis, 0)))
+}
+
+impl ShamirShare {
+    fn encode(&self) -> Zeroizing<Vec<u8>> {
+        let mut body = Zeroizing::new(Vec::with_capacity(FIXED_LEN + self.value.len()));
+        body.extend_from_slice(&self.split_id);
+        body.extend_from_slice(&[self.kind as u8, self.threshold, self.count, self.index]);
+        body.extend_from_slice(&self.value);
+        let checksum = Sha256::digest(&body[..]);
+        body.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        body
+    }
+
+    fn decode(body: &[u8]) -> Result<Self> {
+        if body.len() <= FIXED_LEN {
+            return Err(anyhow!("Shamir share truncated"));
+        }
+        let (content, checksum) = body.split_at(body.len() - CHECKSUM_LEN);
+        if Sha256::digest(content)[..CHECKSUM_LEN] != *checksum {
+            return Err(anyhow!("Shamir share checksum does not match: the share is damaged"));
+        }
+        let (split_id, rest) = content.split_at(SPLIT_ID_LEN);
+        let [kind, threshold, count, index] = rest[..4] else { unreachable!() };
+        if index == 0 || index > count || threshold < 2 || threshold > count {
+            return Err(anyhow!("Shamir share {index} of {count} (threshold {threshold}) is malformed"));
+        }
+        Ok(ShamirShare {
+            split_id: split_id.try_into()?,
+            kind: SecretKind::from_u8(kind)?,
+            threshold,
+            count,
+            index,
+            value: Zeroizing::new(rest[4..].to_vec()),
+        })
+    }
+
+    /// Short hex form of the split ID, to tell splits apart.
+    pub fn split_hex(&self) -> String {
+        hex::encode(&self.split_id[..4])
+    }
+}
+
+/// A share as handed to a custodian: in the clear, or wrapped to their key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoredShare {
+    Plain(ShamirShare),
+    Rsa(Vec<u8>),
+    Hpke { enc: [u8; hpke::ENC_LEN], sealed: Vec<u8> },
+}
+
+impl StoredShare {
+    /// Wraps `share` to `custodian`, or keeps it in the clear.
+    pub fn wrap(share: ShamirShare, custodian: Option<&Recipient>) -> Result<Self> {
+        let body = share.encode();
+        match custodian {
+            None => Ok(StoredShare::Plain(share)),
+            Some(Recipient::Rsa(key)) => Ok(StoredShare::Rsa(rsa_wrap(key, &body)?)),
+            Some(Recipient::X25519(key)) => {
+                let (enc, mut context) = hpke::setup_sender(key, HPKE_INFO, None)?;
+                Ok(StoredShare::Hpke { enc, sealed: context.seal(b"", &body)? })
+            }
+        }
+    }
+
+    /// The share in the clear, unwrapping it with whichever of `keys` fits.
+    pub fn open(self, keys: &[Credential]) -> Result<ShamirShare> {
+        let body = match &self {
+            StoredShare::Plain(_) => None,
+            StoredShare::Rsa(wrapped) => keys.iter().find_map(|key| match key {
+                Credential::RsaPrivateKey(key) => key.decrypt(Oaep::new::<Sha256>(), wrapped).ok().map(Zeroizing::new),
+                _ => None,
+            }),
+            StoredShare::Hpke { enc, sealed } => keys.iter().find_map(|key| match key {
+                Credential::X25519PrivateKey(key) => {
+                    hpke::setup_receiver(enc, key, HPKE_INFO, None).and_then(|mut context| context.open(b"", sealed)).ok()
+                }
+                _ => None,
+            }),
+        };
+        match (self, body) {
+            (StoredShare::Plain(share), _) => Ok(share),
+            (_, Some(body)) => ShamirShare::decode(&body),
+            (StoredShare::Rsa(_), None) => Err(anyhow!("a share is wrapped to an RSA key none of --private-pem opens")),
+            (StoredShare::Hpke { .. }, None) => Err(anyhow!("a share is wrapped to an X25519 key none of --private-pem opens")),
+        }
+    }
+
+    pub fn armor(&self) -> String {
+        match self {
+            StoredShare::Plain(share) => armor(ALGORITHM, &STANDARD.encode(share.encode())),
+            StoredShare::Rsa(wrapped) => armor(RSA_ALGORITHM, &STANDARD.encode(wrapped)),
+            StoredShare::Hpke { enc, sealed } => armor(HPKE_ALGORITHM, &STANDARD.encode([&enc[..], sealed].concat())),
+        }
+    }
+
+    /// Every armored share in `text`, which may hold several.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>> {
+        let mut shares = Vec::new();
+        for block in text.split(ARMOR_BEGIN).skip(1) {
+            let end = block.find(ARMOR_END).ok_or_else(|| anyhow!("share armor is missing its END line"))?;
+            let (algorithm, body) = dearmor(&block[..end + ARMOR_END.len()])?;
+            let body = Zeroizing::new(STANDARD.decode(body).map_err(|e| anyhow!("share body is not base64: {e}"))?);
+            shares.push(match algorithm {
+                ALGORITHM => StoredShare::Plain(ShamirShare::decode(&body)?),
+                RSA_ALGORITHM => StoredShare::Rsa(body.to_vec()),
+                HPKE_ALGORITHM if body.len() > hpke::ENC_LEN => StoredShare::Hpke {
+                    enc: body[..hpke::ENC_LEN].try_into()?,
+                    sealed: body[hpke::ENC_LEN..].to_vec(),
+                },
+                HPKE_ALGORITHM => return Err(anyhow!("HPKE-wrapped Shamir share truncated")),
+                other => return Err(anyhow!("not a Shamir share (algorithm {other})")),
+            });
+        }
+        if shares.is_empty() {
+            return Err(anyhow!("no armored Shamir share found"));
+        }
+        Ok(shares)
+    }
+}
+
+fn rsa_wrap(key: &RsaPublicKey, body: &[u8]) -> Result<Vec<u8>> {
+    key.encrypt(&mut rand::thread_rng(), Oaep::new::<Sha256>(), body)
+        .map_err(|e| anyhow!("cannot wrap a {}-byte share for this RSA key: {e}", body.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::RsaPrivateKey;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    #[test]
+    fn any_threshold_of_shares_recovers_the_secret() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1); // FIPS 197, section 4.2
+        assert!((1..=255u8).all(|a| gf_mul(a, gf_inv(a)) == 1));
+
+        let secret = b"correct horse battery staple";
+        let shares = split(secret, SecretKind::Passphrase, 3, 5).unwrap();
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(combine(&subset).unwrap().1.as_slice(), secret);
+                }
+            }
+        }
+        let two = combine(&shares[..2]).unwrap_err().to_string();
+        assert_eq!(two, "3 of 5 shares are needed, got 2");
+
+        // A fourth share is checked against the other three.
+        let mut forged = shares[3].clone();
+        forged.value[0] ^= 1;
+        let err = combine(&[shares[0].clone(), shares[1].clone(), shares[2].clone(), forged]).unwrap_err();
+        assert!(err.to_string().contains("share 4 does not match the others"));
+        let other_split = split(secret, SecretKind::Passphrase, 3, 5).unwrap();
+        assert!(combine(&[shares[0].clone(), other_split[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn shares_armor_with_a_checksum_and_wrap_to_custodians() {
+        let data_key = [7u8; 32];
+        let shares = split(&data_key, SecretKind::DataKey, 2, 3).unwrap();
+        let rsa = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let x25519 = StaticSecret::random_from_rng(rand::thread_rng());
+        let custodians = [
+            None,
+            Some(Recipient::Rsa(rsa.to_public_key())),
+            Some(Recipient::X25519(PublicKey::from(&x25519))),
+        ];
+        let text: String = shares
+            .iter()
+            .zip(&custodians)
+            .map(|(share, custodian)| StoredShare::wrap(share.clone(), custodian.as_ref()).unwrap().armor())
+            .collect();
+        assert!(text.contains("Algorithm: SHAMIR-GF256-HPKE-X25519-SHA256-CHACHA20POLY1305"));
+
+        let stored = StoredShare::parse_all(&text).unwrap();
+        let keys = [Credential::RsaPrivateKey(Box::new(rsa)), Credential::X25519PrivateKey(Box::new(x25519))];
+        let opened = stored.iter().map(|s| s.clone().open(&keys).unwrap()).collect::<Vec<_>>();
+        assert_eq!(opened, shares);
+        assert_eq!(combine(&opened[1..]).unwrap(), (SecretKind::DataKey, Zeroizing::new(data_key.to_vec())));
+        assert!(stored[2].clone().open(&keys[..1]).unwrap_err().to_string().contains("X25519 key"));
+
+        // One flipped bit in a clear share fails its checksum.
+        let plain = StoredShare::Plain(shares[0].clone()).armor();
+        let mut body = STANDARD.decode(plain.lines().nth(4).unwrap()).unwrap();
+        body[SPLIT_ID_LEN + 4] ^= 1;
+        assert!(ShamirShare::decode(&body).unwrap_err().to_string().contains("checksum does not match"));
+    }
+}
@@ -0,0 +1,409 @@
+//! Share objects: a file passphrase wrapped to recipients' public keys.
+//!
+//! Version 1 is RSA-OAEP with SHA-256 (for both the hash and MGF1) and an
+//! empty label over the UTF-8 passphrase. Its base64 body is exactly the
+//! `encrypted_key` that the server stores for a share, so that bare string is
+//! accepted everywhere an armored share is. The armored form adds the
+//! version and algorithm so later formats can be told apart:
+//!
+//! ```text
+//! -----BEGIN BLOCKVAULT SHARE-----
+//! Version: 1
+//! Algorithm: RSA-OAEP-SHA256
+//!
+//! <base64, 64 columns>
+//! -----END BLOCKVAULT SHARE-----
+//! ```
+//!
+//! `Algorithm: HPKE-X25519-SHA256-CHACHA20POLY1305` wraps the passphrase to
+//! one or more X25519 keys with HPKE (RFC 9180); it has no bare form. Its body
+//! is (integers little-endian):
+//!
+//! ```text
+//! mode        u8  0 = Base, 2 = Auth
+//! sender      32  X25519 public key of the sending owner (Auth mode only)
+//! count       u8
+//! recipients  { public_key 32, enc 32, sealed u16 length + bytes }*
+//! ```
+//!
+//! In Auth mode only the holder of the sender's private key could have
+//! produced the encapsulations, so a recipient who knows the owner's public
+//! key can check who the share came from.
+//!
+//! A share record's `encrypted_key` may also be a JWE (see `jwe`), which
+//! names its own algorithms; `wrap-key --jwe` writes the compact form.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use serde_json::Value;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::hpke::{self, MODE_AUTH, MODE_BASE};
+use crate::jwe::{Jwe, JweKey, DATA_KEY_CTY};
+
+pub const SHARE_VERSION: u32 = 1;
+const ALGORITHM: &str = "RSA-OAEP-SHA256";
+const HPKE_ALGORITHM: &str = "HPKE-X25519-SHA256-CHACHA20POLY1305";
+const HPKE_INFO: &[u8] = b"blockvault share v1";
+pub const ARMOR_BEGIN: &str = "-----BEGIN BLOCKVAULT SHARE-----";
+pub const ARMOR_END: &str = "-----END BLOCKVAULT SHARE-----";
+const LINE_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    /// RSA-OAEP ciphertext of the passphrase.
+    pub wrapped: Vec<u8>,
+}
+
+impl Share {
+    pub fn wrap(passphrase: &str, recipient: &RsaPublicKey) -> Result<Self> {
+        let wrapped = recipient
+            .encrypt(&mut rand::thread_rng(), Oaep::new::<Sha256>(), passphrase.as_bytes())
+            .map_err(|e| anyhow!("cannot wrap the passphrase for this key: {e}"))?;
+        Ok(Share { wrapped })
+    }
+
+    pub fn unwrap(&self, key: &RsaPrivateKey) -> Result<Zeroizing<String>> {
+        let plain = Zeroizing::new(
+            key.decrypt(Oaep::new::<Sha256>(), &self.wrapped)
+                .map_err(|_| anyhow!("share was not wrapped for this private key"))?,
+        );
+        let passphrase = std::str::from_utf8(&plain).map_err(|_| anyhow!("unwrapped passphrase is not UTF-8"))?;
+        Ok(Zeroizing::new(passphrase.to_string()))
+    }
+
+    /// The bare base64 body, as stored in a share's `encrypted_key`.
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(&self.wrapped)
+    }
+
+    pub fn armor(&self) -> String {
+        armor(ALGORITHM, &self.to_base64())
+    }
+
+    /// Parses an armored share or a bare base64 `encrypted_key`.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let Some(armored) = text.strip_prefix(ARMOR_BEGIN) else {
+            return Self::from_base64(text);
+        };
+        match dearmor(armored)? {
+            (ALGORITHM, body) => Self::from_base64(&body),
+            (HPKE_ALGORITHM, _) => Err(anyhow!("this is an HPKE share; open it with `hpke unwrap`")),
+            (other, _) if other.starts_with("SHAMIR-") => Err(anyhow!("this is a Shamir share; recover it with `combine`")),
+            (other, _) => Err(anyhow!("unsupported share algorithm {other}")),
+        }
+    }
+
+    fn from_base64(text: &str) -> Result<Self> {
+        let wrapped = STANDARD.decode(text).map_err(|e| anyhow!("share is neither armored nor base64: {e}"))?;
+        if wrapped.is_empty() {
+            return Err(anyhow!("share is empty"));
+        }
+        Ok(Share { wrapped })
+    }
+}
+
+pub fn armor(algorithm: &str, body: &str) -> String {
+    let mut out = format!("{ARMOR_BEGIN}\nVersion: {SHARE_VERSION}\nAlgorithm: {algorithm}\n\n");
+    for line in body.as_bytes().chunks(LINE_LEN) {
+        out.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        out.push('\n');
+    }
+    out.push_str(ARMOR_END);
+    out.push('\n');
+    out
+}
+
+/// Splits the armor after its BEGIN line into the algorithm and the base64
+/// body with whitespace removed.
+pub fn dearmor(armored: &str) -> Result<(&str, String)> {
+    let inner = armored.strip_suffix(ARMOR_END).ok_or_else(|| anyhow!("share armor is missing its END line"))?;
+    let inner = inner.trim_start_matches(['\r', '\n']);
+    let (headers, body) = inner
+        .split_once("\n\n")
+        .or_else(|| inner.split_once("\r\n\r\n"))
+        .ok_or_else(|| anyhow!("share armor is missing the blank line after its headers"))?;
+    let mut version = None;
+    let mut algorithm = None;
+    for line in headers.lines() {
+        match line.split_once(':').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("Version", v)) => version = Some(v),
+            Some(("Algorithm", v)) => algorithm = Some(v),
+            _ => {}
+        }
+    }
+    match version {
+        Some(v) if v == SHARE_VERSION.to_string() => {}
+        Some(v) => return Err(anyhow!("unsupported share version {v} (this build reads version {SHARE_VERSION})")),
+        None => return Err(anyhow!("share armor has no Version header")),
+    }
+    let algorithm = algorithm.ok_or_else(|| anyhow!("unsupported share algorithm (none)"))?;
+    Ok((algorithm, body.split_whitespace().collect()))
+}
+
+/// One recipient's entry in an HPKE share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HpkeEntry {
+    pub public_key: [u8; 32],
+    pub enc: [u8; hpke::ENC_LEN],
+    pub sealed: Vec<u8>,
+}
+
+/// A passphrase wrapped with HPKE to one or more X25519 recipients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HpkeShare {
+    /// The sending owner's public key; `Some` means Auth mode.
+    pub sender: Option<[u8; 32]>,
+    pub recipients: Vec<HpkeEntry>,
+}
+
+impl HpkeShare {
+    /// Wraps `passphrase` to each recipient, in Auth mode when `sender` is given.
+    pub fn wrap(passphrase: &str, recipients: &[PublicKey], sender: Option<&StaticSecret>) -> Result<Self> {
+        if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+            return Err(anyhow!("an HPKE share needs between 1 and 255 recipients, got {}", recipients.len()));
+        }
+        let recipients = recipients
+            .iter()
+            .map(|key| {
+                let (enc, mut context) = hpke::setup_sender(key, HPKE_INFO, sender)?;
+                Ok(HpkeEntry { public_key: key.to_bytes(), enc, sealed: context.seal(b"", passphrase.as_bytes())? })
+            })
+            .collect::<Result<_>>()?;
+        Ok(HpkeShare { sender: sender.map(|key| PublicKey::from(key).to_bytes()), recipients })
+    }
+
+    /// Unwraps the entry for `key`. With `expected_sender` the share must be
+    /// in Auth mode and sent by that key; without it an Auth-mode share is
+    /// still authenticated against the sender key it names.
+    pub fn unwrap(&self, key: &StaticSecret, expected_sender: Option<&PublicKey>) -> Result<Zeroizing<String>> {
+        match (self.sender, expected_sender) {
+            (None, Some(_)) => return Err(anyhow!("share is not authenticated (Base mode), so its sender cannot be verified")),
+            (Some(sender), Some(expected)) if sender != expected.to_bytes() => {
+                return Err(anyhow!("share was sent by {}, not by the expected sender key", hex::encode(sender)));
+            }
+            _ => {}
+        }
+        let public_key = PublicKey::from(key).to_bytes();
+        let entry = self
+            .recipients
+            .iter()
+            .find(|entry| entry.public_key == public_key)
+            .ok_or_else(|| anyhow!("share was not wrapped for this private key"))?;
+        let sender = self.sender.map(PublicKey::from);
+        let plain = hpke::setup_receiver(&entry.enc, key, HPKE_INFO, sender.as_ref())
+            .and_then(|mut context| context.open(b"", &entry.sealed))
+            .map_err(|_| match sender {
+                Some(_) => anyhow!("share failed sender authentication"),
+                None => anyhow!("share was not wrapped for this private key"),
+            })?;
+        let passphrase = std::str::from_utf8(&plain).map_err(|_| anyhow!("unwrapped passphrase is not UTF-8"))?;
+        Ok(Zeroizing::new(passphrase.to_string()))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self.sender {
+            Some(sender) => {
+                body.push(MODE_AUTH);
+                body.extend_from_slice(&sender);
+            }
+            None => body.push(MODE_BASE),
+        }
+        body.push(self.recipients.len() as u8);
+        for entry in &self.recipients {
+            body.extend_from_slice(&entry.public_key);
+            body.extend_from_slice(&entry.enc);
+            body.extend_from_slice(&(entry.sealed.len() as u16).to_le_bytes());
+            body.extend_from_slice(&entry.sealed);
+        }
+        body
+    }
+
+    fn decode(body: &[u8]) -> Result<Self> {
+        let mut pos = 0;
+        let mut take = |n: usize| -> Result<&[u8]> {
+            let field = body.get(pos..pos + n).ok_or_else(|| anyhow!("HPKE share truncated"))?;
+            pos += n;
+            Ok(field)
+        };
+        let sender = match take(1)?[0] {
+            MODE_BASE => None,
+            MODE_AUTH => Some(take(32)?.try_into()?),
+            mode => return Err(anyhow!("unsupported HPKE share mode {mode}")),
+        };
+        let count = take(1)?[0];
+        let mut recipients = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let public_key = take(32)?.try_into()?;
+            let enc = take(hpke::ENC_LEN)?.try_into()?;
+            let len = u16::from_le_bytes(take(2)?.try_into()?) as usize;
+            recipients.push(HpkeEntry { public_key, enc, sealed: take(len)?.to_vec() });
+        }
+        if pos != body.len() {
+            return Err(anyhow!("HPKE share has trailing bytes"));
+        }
+        Ok(HpkeShare { sender, recipients })
+    }
+
+    pub fn armor(&self) -> String {
+        armor(HPKE_ALGORITHM, &STANDARD.encode(self.encode()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let armored = text.trim().strip_prefix(ARMOR_BEGIN).ok_or_else(|| anyhow!("HPKE shares are always armored"))?;
+        match dearmor(armored)? {
+            (HPKE_ALGORITHM, body) => {
+                Self::decode(&STANDARD.decode(body).map_err(|e| anyhow!("share body is not base64: {e}"))?)
+            }
+            (other, _) => Err(anyhow!("not an HPKE share (algorithm {other})")),
+        }
+    }
+}
+
+/// A wrapped passphrase as the server stores it: a version 1 share or a JWE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SharedKey {
+    Share(Share),
+    Jwe(Box<Jwe>),
+}
+
+impl SharedKey {
+    pub fn parse(text: &str) -> Result<Self> {
+        if Jwe::detect(text) {
+            return Ok(SharedKey::Jwe(Box::new(Jwe::parse(text)?)));
+        }
+        Share::parse(text).map(SharedKey::Share)
+    }
+
+    pub fn unwrap(&self, key: &RsaPrivateKey) -> Result<Zeroizing<String>> {
+        let jwe = match self {
+            SharedKey::Share(share) => return share.unwrap(key),
+            SharedKey::Jwe(jwe) => jwe,
+        };
+        jwe_passphrase(jwe, &JweKey::Rsa(key))
+    }
+}
+
+/// The passphrase inside a JWE, refusing one that carries a data key.
+pub fn jwe_passphrase(jwe: &Jwe, key: &JweKey) -> Result<Zeroizing<String>> {
+    if jwe.content_type() == Some(DATA_KEY_CTY) {
+        return Err(anyhow!("this JWE holds a file's data key, not a passphrase; open the file with `jwe open`"));
+    }
+    let plain = jwe.open(key)?;
+    let passphrase = std::str::from_utf8(&plain).map_err(|_| anyhow!("unwrapped passphrase is not UTF-8"))?;
+    Ok(Zeroizing::new(passphrase.to_string()))
+}
+
+/// A share as received: the wrapped passphrase plus, for a share record
+/// exported from the Sharing Center (JSON), the file's plaintext SHA-256 and
+/// AAD. Armored and bare shares carry neither.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareRecord {
+    pub share: SharedKey,
+    pub sha256: Option<String>,
+    pub aad: Option<String>,
+}
+
+impl ShareRecord {
+    pub fn parse(text: &str) -> Result<Self> {
+        if !text.trim_start().starts_with('{') || Jwe::detect(text) {
+            return Ok(ShareRecord { share: SharedKey::parse(text)?, sha256: None, aad: None });
+        }
+        let record: Value = serde_json::from_str(text).map_err(|e| anyhow!("invalid share record: {e}"))?;
+        let field = |name: &str| match record.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(anyhow!("invalid share record: '{name}' is not a string")),
+        };
+        let encrypted_key = field("encrypted_key")?.ok_or_else(|| anyhow!("share record has no encrypted_key"))?;
+        Ok(ShareRecord { share: SharedKey::parse(&encrypted_key)?, sha256: field("sha256")?, aad: field("aad")? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn armored_and_bare_forms_carry_the_same_bytes() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let share = Share::wrap("correct horse", &key.to_public_key()).unwrap();
+        let armored = share.armor();
+        assert!(armored.lines().all(|l| l.len() <= LINE_LEN || l.starts_with("-----")), "{armored}");
+        assert_eq!(Share::parse(&armored).unwrap(), share);
+        assert_eq!(Share::parse(&share.to_base64()).unwrap(), share);
+        assert_eq!(*Share::parse(&armored).unwrap().unwrap(&key).unwrap(), "correct horse");
+
+        let other = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        assert!(share.unwrap(&other).is_err());
+        assert!(Share::parse(&armored.replace("Version: 1", "Version: 2")).is_err());
+        assert!(Share::parse(&armored.replace(ALGORITHM, "RSA1_5")).is_err());
+        assert!(Share::parse("not base64!").is_err());
+    }
+
+    #[test]
+    fn opens_encrypted_keys_made_outside_the_engine() {
+        // The server and `openssl pkeyutl -pkeyopt rsa_padding_mode:oaep
+        // -pkeyopt rsa_oaep_md:sha256` produce plain OAEP-SHA256 ciphertexts.
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let wrapped = key.to_public_key().encrypt(&mut rand::thread_rng(), Oaep::new::<Sha256>(), b"pw").unwrap();
+        let encrypted_key = STANDARD.encode(&wrapped);
+        assert_eq!(*Share::parse(&encrypted_key).unwrap().unwrap(&key).unwrap(), "pw");
+
+        let json = format!(r#"{{"share_id": "1", "encrypted_key": "{encrypted_key}", "sha256": "ab", "aad": null}}"#);
+        let record = ShareRecord::parse(&json).unwrap();
+        let expected = SharedKey::Share(Share { wrapped });
+        assert_eq!((record.share, record.sha256.as_deref(), record.aad), (expected, Some("ab"), None));
+        assert!(ShareRecord::parse(r#"{"sha256": "ab"}"#).is_err());
+        assert_eq!(ShareRecord::parse(&encrypted_key).unwrap().sha256, None);
+    }
+
+    #[test]
+    fn jwe_encrypted_keys_open_like_version_1_shares() {
+        use crate::slots::Recipient;
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let compact = Jwe::seal(b"pw", None, &[Recipient::Rsa(key.to_public_key())]).unwrap().compact().unwrap();
+        assert_eq!(*ShareRecord::parse(&compact).unwrap().share.unwrap(&key).unwrap(), "pw");
+        let json = format!(r#"{{"encrypted_key": "{compact}", "sha256": "ab"}}"#);
+        let record = ShareRecord::parse(&json).unwrap();
+        assert_eq!((record.share.unwrap(&key).unwrap().as_str(), record.sha256.as_deref()), ("pw", Some("ab")));
+
+        let data_key = Jwe::seal(&[0u8; 32], Some(DATA_KEY_CTY), &[Recipient::Rsa(key.to_public_key())]).unwrap();
+        let err = SharedKey::Jwe(Box::new(data_key)).unwrap(&key).unwrap_err();
+        assert!(err.to_string().contains("jwe open"), "{err}");
+    }
+
+    #[test]
+    fn hpke_shares_reach_every_recipient_and_authenticate_the_sender() {
+        let new_key = || StaticSecret::random_from_rng(rand::thread_rng());
+        let (alice, bob, owner, impostor) = (new_key(), new_key(), new_key(), new_key());
+        let recipients = [PublicKey::from(&alice), PublicKey::from(&bob)];
+        let share = HpkeShare::wrap("correct horse", &recipients, Some(&owner)).unwrap();
+        let armored = share.armor();
+        assert!(armored.contains(HPKE_ALGORITHM), "{armored}");
+        let parsed = HpkeShare::parse(&armored).unwrap();
+        assert_eq!(parsed, share);
+
+        let owner_pub = PublicKey::from(&owner);
+        assert_eq!(*parsed.unwrap(&alice, Some(&owner_pub)).unwrap(), "correct horse");
+        assert_eq!(*parsed.unwrap(&bob, None).unwrap(), "correct horse");
+        assert!(parsed.unwrap(&impostor, None).is_err());
+        assert!(parsed.unwrap(&alice, Some(&PublicKey::from(&impostor))).is_err());
+
+        // Swapping in another sender key breaks the Auth-mode KEM.
+        let forged = HpkeShare { sender: Some(PublicKey::from(&impostor).to_bytes()), ..parsed.clone() };
+        let err = forged.unwrap(&alice, None).unwrap_err();
+        assert!(err.to_string().contains("sender authentication"), "{err}");
+
+        let base = HpkeShare::wrap("pw", &recipients[..1], None).unwrap();
+        assert_eq!(*base.unwrap(&alice, None).unwrap(), "pw");
+        assert!(base.unwrap(&alice, Some(&owner_pub)).is_err(), "Base mode cannot prove a sender");
+        assert!(Share::parse(&armored).unwrap_err().to_string().contains("hpke unwrap"));
+        assert!(HpkeShare::parse(&share.armor().replace("Version: 1", "Version: 2")).is_err());
+    }
+}
@@ -0,0 +1,667 @@
+//! Key slots: LUKS-style envelope encryption for BVENC002.
+//!
+//! A file is encrypted under a random 256-bit data key. Each slot wraps that
+//! key independently, either under a passphrase (with its own KDF parameters
+//! and salt), to a public key (RSA-OAEP, or HPKE with X25519) or under a key
+//! derived from a master key (see `master`), so access can be granted or revoked
+//! without re-encrypting the payload or revealing anyone's passphrase.
+//!
+//! The slot area sits between the header tag and the first segment. Its size
+//! is fixed when the file is created and recorded in the critical
+//! `EXT_KEY_SLOTS` header extension; the area is *not* part of the segment
+//! associated data, so it can be rewritten in place. It holds two copies,
+//! each laid out as (integers little-endian):
+//!
+//! ```text
+//! generation u64
+//! count      u8
+//! slots      { type u8, label u8 length + bytes, body u16 length + bytes }*
+//! padding    zeros
+//! mac        32  HMAC-SHA256(data key, label || header || copy without mac)
+//! ```
+//!
+//! Updates write the stale copy with a higher generation first and only then
+//! the other one, syncing in between, so a crash at any point leaves at least
+//! one complete copy. Readers trust the newest copy whose MAC verifies under
+//! the unwrapped data key.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::Aes256Gcm;
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePublicKey};
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+
+use crate::{age_format, hpke};
+use crate::kdf::KdfParams;
+use crate::master::{FileKey, MasterKey, FILE_ID_LEN, MASTER_ID_LEN};
+
+pub const DEFAULT_SLOT_AREA: u32 = 4096;
+pub const MIN_SLOT_AREA: u32 = 256;
+pub const MAX_SLOT_AREA: u32 = 1 << 20;
+const MAC_LEN: usize = 32;
+const MAC_LABEL: &[u8] = b"blockvault key slots v1";
+const WRAP_NONCE_LEN: usize = 12;
+const WRAP_AAD: &[u8] = b"blockvault key slot v1";
+const SLOT_PASSPHRASE: u8 = 1;
+const SLOT_RSA_OAEP: u8 = 2;
+const SLOT_HPKE: u8 = 3;
+const SLOT_MASTER: u8 = 4;
+
+pub type DataKey = Zeroizing<[u8; 32]>;
+
+/// What the caller presents to open a file.
+pub enum Credential {
+    Passphrase(Zeroizing<String>),
+    RsaPrivateKey(Box<RsaPrivateKey>),
+    X25519PrivateKey(Box<StaticSecret>),
+    /// age or OpenSSH identities; these only open age files.
+    AgeIdentities(Vec<Box<dyn age::Identity>>),
+    /// The data key itself, e.g. delivered as a JWE; it bypasses the slots.
+    DataKey(DataKey),
+    /// An unlocked master key; opens `hkdf-master` slots of that master.
+    MasterKey(Box<MasterKey>),
+    /// One file's key derived from a master key, as `master derive` discloses it.
+    FileKey(FileKey),
+}
+
+impl From<&str> for Credential {
+    fn from(passphrase: &str) -> Self {
+        Credential::Passphrase(Zeroizing::new(passphrase.to_string()))
+    }
+}
+
+impl Credential {
+    /// Error for a credential that matches no slot.
+    pub fn rejected(&self) -> anyhow::Error {
+        match self {
+            Credential::Passphrase(_) => anyhow!("wrong passphrase"),
+            Credential::RsaPrivateKey(_) | Credential::X25519PrivateKey(_) => {
+                anyhow!("no key slot opens with this private key")
+            }
+            Credential::AgeIdentities(_) => anyhow!("age and SSH identities only open age files"),
+            Credential::DataKey(_) => anyhow!("the data key does not belong to this file"),
+            Credential::MasterKey(_) => anyhow!("no key slot of this file was made with this master key"),
+            Credential::FileKey(_) => anyhow!("the file key does not open any key slot of this file"),
+        }
+    }
+}
+
+pub fn load_public_pem(path: &Path) -> Result<RsaPublicKey> {
+    let pem = fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?;
+    RsaPublicKey::from_public_key_pem(&pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+        .map_err(|_| anyhow!("{} is not a PEM RSA public key", path.display()))
+}
+
+pub fn load_private_pem(path: &Path) -> Result<RsaPrivateKey> {
+    let pem = Zeroizing::new(fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?);
+    RsaPrivateKey::from_pkcs8_pem(&pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+        .map_err(|_| anyhow!("{} is not an unencrypted PEM RSA private key", path.display()))
+}
+
+/// A public key a file or share can be wrapped to.
+#[derive(Debug, Clone)]
+pub enum Recipient {
+    Rsa(RsaPublicKey),
+    X25519(X25519PublicKey),
+}
+
+/// Loads an X25519 or RSA public key PEM, whichever `path` holds.
+pub fn load_recipient_pem(path: &Path) -> Result<Recipient> {
+    let pem = fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?;
+    if let Some(key) = hpke::parse_public_pem(&pem) {
+        return Ok(Recipient::X25519(key));
+    }
+    RsaPublicKey::from_public_key_pem(&pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+        .map(Recipient::Rsa)
+        .map_err(|_| anyhow!("{} is not a PEM X25519 or RSA public key", path.display()))
+}
+
+pub fn load_x25519_private_pem(path: &Path) -> Result<StaticSecret> {
+    let pem = Zeroizing::new(fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?);
+    hpke::parse_private_pem(&pem).ok_or_else(|| anyhow!("{} is not an unencrypted PEM X25519 private key", path.display()))
+}
+
+pub fn load_x25519_public_pem(path: &Path) -> Result<X25519PublicKey> {
+    match load_recipient_pem(path)? {
+        Recipient::X25519(key) => Ok(key),
+        Recipient::Rsa(_) => Err(anyhow!("{} is an RSA key; an X25519 public key is needed", path.display())),
+    }
+}
+
+/// Loads an X25519 or RSA private key PEM, an age identity file or an
+/// OpenSSH private key as a credential.
+pub fn load_credential_pem(path: &Path) -> Result<Credential> {
+    let text = Zeroizing::new(fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?);
+    if let Some(key) = hpke::parse_private_pem(&text) {
+        return Ok(Credential::X25519PrivateKey(Box::new(key)));
+    }
+    if let Ok(key) = RsaPrivateKey::from_pkcs8_pem(&text).or_else(|_| RsaPrivateKey::from_pkcs1_pem(&text)) {
+        return Ok(Credential::RsaPrivateKey(Box::new(key)));
+    }
+    age_format::parse_identities(&text)?.map(Credential::AgeIdentities).ok_or_else(|| {
+        anyhow!("{} is not an unencrypted X25519 or RSA PEM key, age identity file or OpenSSH key", path.display())
+    })
+}
+
+/// SHA-256 of the DER SubjectPublicKeyInfo, as shown by `slot list`.
+pub fn rsa_fingerprint(key: &RsaPublicKey) -> Result<[u8; 32]> {
+    let der = key.to_public_key_der().map_err(|e| anyhow!("cannot encode public key: {e}"))?;
+    Ok(Sha256::digest(der.as_bytes()).into())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotKind {
+    Passphrase { kdf: KdfParams, salt: Vec<u8>, nonce: [u8; WRAP_NONCE_LEN], wrapped: Vec<u8> },
+    RsaOaep { fingerprint: [u8; 32], wrapped: Vec<u8> },
+    /// HPKE Base mode to an X25519 key; `wrapped` is sealed under the context.
+    Hpke { public_key: [u8; 32], enc: [u8; hpke::ENC_LEN], wrapped: Vec<u8> },
+    /// Wrapped under the master key's file key for (`file_id`, `context`).
+    Master {
+        master_id: [u8; MASTER_ID_LEN],
+        file_id: [u8; FILE_ID_LEN],
+        context: String,
+        nonce: [u8; WRAP_NONCE_LEN],
+        wrapped: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySlot {
+    pub label: String,
+    pub kind: SlotKind,
+}
+
+fn wrap_cipher(kek: &[u8; 32]) -> Aes256Gcm {
+    Aes256Gcm::new(kek.into())
+}
+
+/// Refuses a label that the slot encoding (a u8 length) cannot hold.
+fn check_label(label: &str) -> Result<()> {
+    if label.is_empty() || label.len() > u8::MAX as usize {
+        return Err(anyhow!("key slot label must be 1 to 255 bytes, got {}", label.len()));
+    }
+    Ok(())
+}
+
+/// `wrapped` decrypted under `kek`, or `None` if it does not authenticate.
+fn open_wrapped(kek: &[u8; 32], nonce: &[u8; WRAP_NONCE_LEN], wrapped: &[u8]) -> Option<Vec<u8>> {
+    let mut buf = wrapped.to_vec();
+    wrap_cipher(kek).decrypt_in_place(nonce.into(), WRAP_AAD, &mut buf).ok()?;
+    Some(buf)
+}
+
+impl KeySlot {
+    /// Wraps `dek` under a key derived from `passphrase`. A slot copied into
+    /// another file yields a key that fails that file's key commitment.
+    pub fn passphrase(label: &str, passphrase: &str, kdf: KdfParams, dek: &[u8; 32]) -> Result<Self> {
+        check_label(label)?;
+        let mut salt = vec![0u8; 16];
+        let mut nonce = [0u8; WRAP_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let kek = kdf.derive(passphrase.as_bytes(), &salt)?;
+        let mut wrapped = dek.to_vec();
+        wrap_cipher(&kek)
+            .encrypt_in_place((&nonce).into(), WRAP_AAD, &mut wrapped)
+            .map_err(|e| anyhow!("key wrapping failed: {e}"))?;
+        Ok(KeySlot { label: label.to_string(), kind: SlotKind::Passphrase { kdf, salt, nonce, wrapped } })
+    }
+
+    /// Wraps `dek` to an RSA public key with RSA-OAEP (SHA-256).
+    pub fn rsa(label: &str, key: &RsaPublicKey, dek: &[u8; 32]) -> Result<Self> {
+        check_label(label)?;
+        let wrapped = key
+            .encrypt(&mut rand::thread_rng(), Oaep::new::<Sha256>(), dek)
+            .map_err(|e| anyhow!("RSA-OAEP wrapping failed: {e}"))?;
+        Ok(KeySlot { label: label.to_string(), kind: SlotKind::RsaOaep { fingerprint: rsa_fingerprint(key)?, wrapped } })
+    }
+
+    /// Wraps `dek` to an X25519 key with HPKE (Base mode).
+    pub fn hpke(label: &str, key: &X25519PublicKey, dek: &[u8; 32]) -> Result<Self> {
+        check_label(label)?;
+        let (enc, mut context) = hpke::setup_sender(key, WRAP_AAD, None)?;
+        let wrapped = context.seal(b"", dek)?;
+        Ok(KeySlot { label: label.to_string(), kind: SlotKind::Hpke { public_key: key.to_bytes(), enc, wrapped } })
+    }
+
+    /// Wraps `dek` under the key `master` derives for a fresh file ID and
+    /// `context`; the slot keeps only that derivation path.
+    pub fn master(label: &str, master: &MasterKey, context: &str, dek: &[u8; 32]) -> Result<Self> {
+        check_label(label)?;
+        let mut file_id = [0u8; FILE_ID_LEN];
+        let mut nonce = [0u8; WRAP_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut file_id);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut wrapped = dek.to_vec();
+        wrap_cipher(&master.file_key(&file_id, context))
+            .encrypt_in_place((&nonce).into(), WRAP_AAD, &mut wrapped)
+            .map_err(|e| anyhow!("key wrapping failed: {e}"))?;
+        let kind = SlotKind::Master { master_id: master.id(), file_id, context: context.to_string(), nonce, wrapped };
+        Ok(KeySlot { label: label.to_string(), kind })
+    }
+
+    /// The file key that opens this slot, if it is an `hkdf-master` slot of `master`.
+    pub fn file_key(&self, master: &MasterKey) -> Option<FileKey> {
+        match &self.kind {
+            SlotKind::Master { master_id, file_id, context, .. } if *master_id == master.id() => {
+                Some(master.file_key(file_id, context))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn for_recipient(label: &str, recipient: &Recipient, dek: &[u8; 32]) -> Result<Self> {
+        match recipient {
+            Recipient::Rsa(key) => Self::rsa(label, key, dek),
+            Recipient::X25519(key) => Self::hpke(label, key, dek),
+        }
+    }
+
+    /// Returns the data key if `cred` opens this slot.
+    pub fn unwrap(&self, cred: &Credential) -> Result<Option<DataKey>> {
+        let mut plain = Zeroizing::new(match (&self.kind, cred) {
+            (SlotKind::Passphrase { kdf, salt, nonce, wrapped }, Credential::Passphrase(passphrase)) => {
+                let kek = kdf.derive(passphrase.as_bytes(), salt)?;
+                let mut buf = wrapped.clone();
+                if wrap_cipher(&kek).decrypt_in_place(nonce.into(), WRAP_AAD, &mut buf).is_err() {
+                    return Ok(None);
+                }
+                buf
+            }
+            (SlotKind::RsaOaep { fingerprint, wrapped }, Credential::RsaPrivateKey(key)) => {
+                if rsa_fingerprint(&key.to_public_key())? != *fingerprint {
+                    return Ok(None);
+                }
+                match key.decrypt(Oaep::new::<Sha256>(), wrapped) {
+                    Ok(buf) => buf,
+                    Err(_) => return Ok(None),
+                }
+            }
+            (SlotKind::Hpke { public_key, enc, wrapped }, Credential::X25519PrivateKey(key)) => {
+                if X25519PublicKey::from(&**key).as_bytes() != public_key {
+                    return Ok(None);
+                }
+                match hpke::setup_receiver(enc, key, WRAP_AAD, None).and_then(|mut c| c.open(b"", wrapped)) {
+                    Ok(buf) => buf.to_vec(),
+                    Err(_) => return Ok(None),
+                }
+            }
+            (SlotKind::Master { nonce, wrapped, .. }, Credential::MasterKey(master)) => {
+                match self.file_key(master).and_then(|kek| open_wrapped(&kek, nonce, wrapped)) {
+                    Some(buf) => buf,
+                    None => return Ok(None),
+                }
+            }
+            (SlotKind::Master { nonce, wrapped, .. }, Credential::FileKey(kek)) => match open_wrapped(kek, nonce, wrapped) {
+                Some(buf) => buf,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        });
+        let Ok(dek) = <[u8; 32]>::try_from(plain.as_slice()) else {
+            return Ok(None);
+        };
+        plain.fill(0);
+        Ok(Some(Zeroizing::new(dek)))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.kind {
+            SlotKind::Passphrase { .. } => "passphrase",
+            SlotKind::RsaOaep { .. } => "rsa-oaep-sha256",
+            SlotKind::Hpke { .. } => "hpke-x25519",
+            SlotKind::Master { .. } => "hkdf-master",
+        }
+    }
+
+    /// Public description for `slot list` and `inspect`.
+    pub fn describe(&self) -> Value {
+        let mut value = json!({ "type": self.type_name(), "label": self.label });
+        match &self.kind {
+            SlotKind::Passphrase { kdf, .. } => {
+                value["kdf"] = kdf.name().into();
+                for (name, cost) in kdf.costs() {
+                    value[name] = cost.into();
+                }
+            }
+            SlotKind::RsaOaep { fingerprint, .. } => value["fingerprint"] = hex::encode(fingerprint).into(),
+            SlotKind::Hpke { public_key, .. } => value["public_key"] = hex::encode(public_key).into(),
+            SlotKind::Master { master_id, file_id, context, .. } => {
+                value["master_id"] = hex::encode(master_id).into();
+                value["file_id"] = hex::encode(file_id).into();
+                value["context"] = context.as_str().into();
+            }
+        }
+        value
+    }
+
+    fn encode_body(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match &self.kind {
+            SlotKind::Passphrase { kdf, salt, nonce, wrapped } => {
+                body.push(kdf.id());
+                put_short(&mut body, &kdf.encode());
+                put_short(&mut body, salt);
+                body.extend_from_slice(nonce);
+                body.extend_from_slice(wrapped);
+            }
+            SlotKind::RsaOaep { fingerprint, wrapped } => {
+                body.extend_from_slice(fingerprint);
+                body.extend_from_slice(wrapped);
+            }
+            SlotKind::Hpke { public_key, enc, wrapped } => {
+                body.extend_from_slice(public_key);
+                body.extend_from_slice(enc);
+                body.extend_from_slice(wrapped);
+            }
+            SlotKind::Master { master_id, file_id, context, nonce, wrapped } => {
+                body.extend_from_slice(master_id);
+                body.extend_from_slice(file_id);
+                put_short(&mut body, context.as_bytes());
+                body.extend_from_slice(nonce);
+                body.extend_from_slice(wrapped);
+            }
+        }
+        body
+    }
+
+    fn decode(kind: u8, label: String, body: &[u8]) -> Result<Self> {
+        let mut c = Cursor(body);
+        let kind = match kind {
+            SLOT_PASSPHRASE => {
+                let kdf_id = c.take(1)?[0];
+                let kdf = KdfParams::decode(kdf_id, c.short()?)?;
+                let salt = c.short()?.to_vec();
+                let nonce = c.take(WRAP_NONCE_LEN)?.try_into()?;
+                SlotKind::Passphrase { kdf, salt, nonce, wrapped: c.0.to_vec() }
+            }
+            SLOT_RSA_OAEP => {
+                let fingerprint = c.take(32)?.try_into()?;
+                SlotKind::RsaOaep { fingerprint, wrapped: c.0.to_vec() }
+            }
+            SLOT_HPKE => {
+                let public_key = c.take(32)?.try_into()?;
+                let enc = c.take(hpke::ENC_LEN)?.try_into()?;
+                SlotKind::Hpke { public_key, enc, wrapped: c.0.to_vec() }
+            }
+            SLOT_MASTER => {
+                let master_id = c.take(MASTER_ID_LEN)?.try_into()?;
+                let file_id = c.take(FILE_ID_LEN)?.try_into()?;
+                let context = String::from_utf8(c.short()?.to_vec()).map_err(|_| anyhow!("key slot context is not UTF-8"))?;
+                let nonce = c.take(WRAP_NONCE_LEN)?.try_into()?;
+                SlotKind::Master { master_id, file_id, context, nonce, wrapped: c.0.to_vec() }
+            }
+            other => return Err(anyhow!("unsupported key slot type {other}")),
+        };
+        Ok(KeySlot { label, kind })
+    }
+
+    fn type_id(&self) -> u8 {
+        match self.kind {
+            SlotKind::Passphrase { .. } => SLOT_PASSPHRASE,
+            SlotKind::RsaOaep { .. } => SLOT_RSA_OAEP,
+            SlotKind::Hpke { .. } => SLOT_HPKE,
+            SlotKind::Master { .. } => SLOT_MASTER,
+        }
+    }
+}
+
+fn put_short(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push(u8::try_from(bytes.len()).expect("slot field longer than 255 bytes"));
+    out.extend_from_slice(bytes);
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(anyhow!("key slot area truncated"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn short(&mut self) -> Result<&'a [u8]> {
+        let len = self.take(1)?[0] as usize;
+        self.take(len)
+    }
+}
+
+fn area_mac(dek: &[u8; 32], header: &[u8], body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(dek).expect("hmac accepts any key length");
+    mac.update(MAC_LABEL);
+    mac.update(header);
+    mac.update(body);
+    mac
+}
+
+/// One copy of the slot area.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotArea {
+    pub generation: u64,
+    pub slots: Vec<KeySlot>,
+}
+
+impl SlotArea {
+    /// Serializes one copy of `capacity` bytes, MACed under `dek`.
+    pub fn encode(&self, capacity: u32, dek: &[u8; 32], header: &[u8]) -> Result<Vec<u8>> {
+        let mut out = self.generation.to_le_bytes().to_vec();
+        out.push(u8::try_from(self.slots.len()).map_err(|_| anyhow!("too many key slots"))?);
+        for slot in &self.slots {
+            let body = slot.encode_body();
+            out.push(slot.type_id());
+            put_short(&mut out, slot.label.as_bytes());
+            out.extend_from_slice(&u16::try_from(body.len()).map_err(|_| anyhow!("key slot too large"))?.to_le_bytes());
+            out.extend_from_slice(&body);
+        }
+        let room = capacity as usize - MAC_LEN;
+        if out.len() > room {
+            return Err(anyhow!(
+                "key slots need {} bytes but the slot area holds {room}; re-encrypt with a larger --slot-area",
+                out.len()
+            ));
+        }
+        out.resize(room, 0);
+        let mac = area_mac(dek, header, &out).finalize().into_bytes();
+        out.extend_from_slice(&mac);
+        Ok(out)
+    }
+
+    /// Parses one copy without checking its MAC.
+    pub fn decode(copy: &[u8]) -> Result<Self> {
+        if copy.len() < MAC_LEN {
+            return Err(anyhow!("key slot area truncated"));
+        }
+        let mut c = Cursor(&copy[..copy.len() - MAC_LEN]);
+        let generation = u64::from_le_bytes(c.take(8)?.try_into()?);
+        let count = c.take(1)?[0];
+        let mut slots = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let kind = c.take(1)?[0];
+            let label = String::from_utf8(c.short()?.to_vec()).map_err(|_| anyhow!("key slot label is not UTF-8"))?;
+            let len = u16::from_le_bytes(c.take(2)?.try_into()?) as usize;
+            slots.push(KeySlot::decode(kind, label, c.take(len)?)?);
+        }
+        Ok(SlotArea { generation, slots })
+    }
+
+    fn verify(copy: &[u8], dek: &[u8; 32], header: &[u8]) -> bool {
+        copy.len() >= MAC_LEN && {
+            let (body, mac) = copy.split_at(copy.len() - MAC_LEN);
+            area_mac(dek, header, body).verify_slice(mac).is_ok()
+        }
+    }
+
+    /// Best-effort view of the newest parseable copy, for callers without a
+    /// credential. Nothing in it is authenticated.
+    pub fn newest_unverified(area: &[u8]) -> Option<SlotArea> {
+        area.chunks(area.len() / 2).filter_map(|copy| SlotArea::decode(copy).ok()).max_by_key(|a| a.generation)
+    }
+}
+
+/// Opens the two-copy slot `area` with `cred`, returning the data key, the
+/// authoritative copy and the index of the slot that opened.
+pub fn unlock(area: &[u8], cred: &Credential, header: &[u8]) -> Result<(DataKey, SlotArea, usize)> {
+    let mut parsed: Vec<(&[u8], SlotArea)> = area
+        .chunks(area.len() / 2)
+        .filter_map(|copy| SlotArea::decode(copy).ok().map(|a| (copy, a)))
+        .collect();
+    if parsed.is_empty() {
+        return Err(anyhow!("key slot area is corrupted"));
+    }
+    parsed.sort_by_key(|(_, a)| std::cmp::Reverse(a.generation));
+    // The copies usually hold the same slots; run each slot's KDF only once.
+    let mut tried: Vec<(&KeySlot, Option<DataKey>)> = Vec::new();
+    let mut unauthenticated = false;
+    for (copy, candidate) in &parsed {
+        for (index, slot) in candidate.slots.iter().enumerate() {
+            let dek = match tried.iter().find(|(s, _)| *s == slot) {
+                Some((_, dek)) => dek.clone(),
+                None => {
+                    let dek = slot.unwrap(cred)?;
+                    tried.push((slot, dek.clone()));
+                    dek
+                }
+            };
+            let Some(dek) = dek else { continue };
+            if !SlotArea::verify(copy, &dek, header) {
+                // A torn or forged copy; the other one may still hold this slot.
+                unauthenticated = true;
+                break;
+            }
+            let newest = parsed
+                .iter()
+                .filter(|(c, _)| SlotArea::verify(c, &dek, header))
+                .map(|(_, a)| a.generation)
+                .max();
+            if newest != Some(candidate.generation) {
+                return Err(anyhow!("{}: its key slot has been removed from this file", cred.rejected()));
+            }
+            return Ok((dek, candidate.clone(), index));
+        }
+    }
+    if unauthenticated {
+        return Err(anyhow!("header tampered: key slot area failed authentication"));
+    }
+    Err(cred.rejected())
+}
+
+/// Replaces the slot area at `offset` in `file` with `next`, one copy at a
+/// time so that an interrupted update leaves one valid copy behind.
+pub fn rewrite(file: &mut fs::File, offset: u64, capacity: u32, next: &SlotArea, dek: &[u8; 32], header: &[u8]) -> Result<()> {
+    let copy = next.encode(capacity, dek, header)?;
+    let mut area = vec![0u8; 2 * capacity as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut area)?;
+    // Overwrite the stale (older or damaged) copy first.
+    let (first, second) = area.split_at(capacity as usize);
+    let stale = match (SlotArea::verify(first, dek, header), SlotArea::verify(second, dek, header)) {
+        (true, true) => {
+            let gens: Vec<u64> = area
+                .chunks(capacity as usize)
+                .map(|c| SlotArea::decode(c).map_or(0, |a| a.generation))
+                .collect();
+            usize::from(gens[1] < gens[0])
+        }
+        (false, _) => 0,
+        (true, false) => 1,
+    };
+    for index in [stale, 1 - stale] {
+        file.seek(SeekFrom::Start(offset + index as u64 * capacity as u64))?;
+        file.write_all(&copy)?;
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"header bytes";
+
+    fn fast_kdf() -> KdfParams {
+        KdfParams::Argon2id { memory_kib: 64, time: 1, parallelism: 1 }
+    }
+
+    fn two_copies(area: &SlotArea, dek: &[u8; 32]) -> Vec<u8> {
+        area.encode(1024, dek, HEADER).unwrap().repeat(2)
+    }
+
+    #[test]
+    fn passphrase_and_rsa_slots_unwrap_the_same_key() {
+        let dek = [7u8; 32];
+        let rsa = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let slots = vec![
+            KeySlot::passphrase("owner", "pw", fast_kdf(), &dek).unwrap(),
+            KeySlot::rsa("alice", &rsa.to_public_key(), &dek).unwrap(),
+        ];
+        let area = two_copies(&SlotArea { generation: 1, slots }, &dek);
+        assert_eq!(*unlock(&area, &"pw".into(), HEADER).unwrap().0, dek);
+        assert_eq!(*unlock(&area, &Credential::RsaPrivateKey(Box::new(rsa)), HEADER).unwrap().0, dek);
+
+        let err = unlock(&area, &"nope".into(), HEADER).unwrap_err();
+        assert_eq!(err.to_string(), "wrong passphrase");
+        let other = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        assert!(unlock(&area, &Credential::RsaPrivateKey(Box::new(other)), HEADER).is_err());
+        // The area MAC binds the slots to the header they were created for.
+        let err = unlock(&area, &"pw".into(), b"other header").unwrap_err();
+        assert!(err.to_string().starts_with("header tampered"), "{err}");
+
+        let decoded = SlotArea::newest_unverified(&area).unwrap();
+        assert_eq!(decoded.slots[1].describe()["label"], "alice");
+        assert_eq!(decoded.slots[0].describe()["kdf"], "argon2id");
+
+        let long = "x".repeat(256);
+        let err = KeySlot::rsa(&long, &RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap().to_public_key(), &dek);
+        assert_eq!(err.unwrap_err().to_string(), "key slot label must be 1 to 255 bytes, got 256");
+        assert!(KeySlot::passphrase("", "pw", fast_kdf(), &dek).is_err());
+    }
+
+    #[test]
+    fn newest_authentic_copy_wins() {
+        let dek = [9u8; 32];
+        let old = KeySlot::passphrase("old", "old-pw", fast_kdf(), &dek).unwrap();
+        let new = KeySlot::passphrase("new", "new-pw", fast_kdf(), &dek).unwrap();
+        let gen1 = SlotArea { generation: 1, slots: vec![old] }.encode(1024, &dek, HEADER).unwrap();
+        let gen2 = SlotArea { generation: 2, slots: vec![new] }.encode(1024, &dek, HEADER).unwrap();
+
+        // Interrupted update: one copy already replaced. The old passphrase is
+        // refused because the newer authentic copy dropped its slot.
+        let area = [gen2.clone(), gen1.clone()].concat();
+        assert!(unlock(&area, &"new-pw".into(), HEADER).is_ok());
+        let err = unlock(&area, &"old-pw".into(), HEADER).unwrap_err();
+        assert!(err.to_string().contains("removed"), "{err}");
+
+        // Torn write of the newer copy: the older one still opens.
+        let mut torn = gen2.clone();
+        torn[20] ^= 1;
+        let area = [gen1.clone(), torn].concat();
+        assert_eq!(unlock(&area, &"old-pw".into(), HEADER).unwrap().1.generation, 1);
+
+        // Slots cannot be squeezed past capacity.
+        let many = SlotArea { generation: 1, slots: vec![gen_slot(&dek); 20] };
+        assert!(many.encode(1024, &dek, HEADER).unwrap_err().to_string().contains("--slot-area"));
+    }
+
+    fn gen_slot(dek: &[u8; 32]) -> KeySlot {
+        KeySlot::passphrase("x", "pw", fast_kdf(), dek).unwrap()
+    }
+}
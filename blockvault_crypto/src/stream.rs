@@ -0,0 +1,530 @@
+//! Segmented streaming AEAD used by the BVENC002 container.
+//!
+//! The plaintext is cut into fixed-size segments and every segment is sealed on
+//! its own (the STREAM construction). Segment `i` uses the nonce
+//! `prefix || i (u32 BE) || last`, where `last` is 1 only for the final
+//! segment, so truncation, reordering and duplicated segments all surface as
+//! authentication failures. Only one segment is held in memory at a time.
+//!
+//! The serialized file header is authenticated twice: by a header tag (an
+//! empty message sealed under the reserved flag value 2, with the header as
+//! associated data) that lets `decrypt` reject a tampered header up front,
+//! and by being part of every segment's associated data.
+//!
+//! Header extensions that must stay confidential (such as the true length of
+//! a padded file) are sealed with flag value 3 and the extension type as the
+//! counter, which keeps their nonces distinct from every segment's.
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use zeroize::Zeroize;
+
+use crate::cipher::{SegmentCipher, TAG_LEN};
+
+pub const NONCE_SUFFIX_LEN: usize = 5; // u32 counter + flag byte
+pub const DEFAULT_SEGMENT_SIZE: u32 = 64 * 1024;
+pub const MAX_SEGMENT_SIZE: u32 = 16 * 1024 * 1024;
+const FLAG_HEADER: u8 = 2; // never used by a segment, so the header tag nonce is unique
+const FLAG_HEADER_FIELD: u8 = 3;
+
+fn segment_nonce(prefix: &[u8], counter: u32, flag: u8) -> Vec<u8> {
+    let mut nonce = Vec::with_capacity(prefix.len() + NONCE_SUFFIX_LEN);
+    nonce.extend_from_slice(prefix);
+    nonce.extend_from_slice(&counter.to_be_bytes());
+    nonce.push(flag);
+    nonce
+}
+
+/// Computes the tag that follows the serialized header.
+pub fn header_tag(cipher: &SegmentCipher, prefix: &[u8], header: &[u8]) -> io::Result<Vec<u8>> {
+    let mut tag = Vec::with_capacity(TAG_LEN);
+    cipher
+        .encrypt_in_place(&segment_nonce(prefix, 0, FLAG_HEADER), header, &mut tag)
+        .map_err(|e| io::Error::other(format!("encryption failed: {e}")))?;
+    Ok(tag)
+}
+
+/// Checks a header tag produced by [`header_tag`].
+pub fn verify_header_tag(cipher: &SegmentCipher, prefix: &[u8], header: &[u8], tag: &[u8]) -> bool {
+    let mut buf = tag.to_vec();
+    cipher.decrypt_in_place(&segment_nonce(prefix, 0, FLAG_HEADER), header, &mut buf).is_ok()
+}
+
+/// Encrypts the value of header extension `kind`.
+pub fn seal_header_field(cipher: &SegmentCipher, prefix: &[u8], kind: u16, value: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buf = value.to_vec();
+    cipher
+        .encrypt_in_place(&segment_nonce(prefix, kind as u32, FLAG_HEADER_FIELD), &[], &mut buf)
+        .map_err(|e| io::Error::other(format!("encryption failed: {e}")))?;
+    Ok(buf)
+}
+
+/// Opens a value sealed by [`seal_header_field`] for the same `kind`.
+pub fn open_header_field(cipher: &SegmentCipher, prefix: &[u8], kind: u16, sealed: &[u8]) -> Option<Vec<u8>> {
+    let mut buf = sealed.to_vec();
+    cipher
+        .decrypt_in_place(&segment_nonce(prefix, kind as u32, FLAG_HEADER_FIELD), &[], &mut buf)
+        .ok()?;
+    Some(buf)
+}
+
+/// Decrypts one sealed segment in place, mapping failure to the error that
+/// `decrypt` reports for corrupted ciphertext.
+fn open_in_place(cipher: &SegmentCipher, prefix: &[u8], aad: &[u8], counter: u32, last: bool, buf: &mut Vec<u8>) -> io::Result<()> {
+    let nonce = segment_nonce(prefix, counter, last as u8);
+    cipher.decrypt_in_place(&nonce, aad, buf).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "corrupted ciphertext: segment {counter} failed authentication (modified, truncated, reordered or wrong --aad)"
+            ),
+        )
+    })
+}
+
+/// Reads until `buf` is full or EOF, returning the number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encrypts everything written to it into sealed segments on `inner`.
+///
+/// A full segment is only sealed once more data arrives, so that the final
+/// segment can carry the end-of-stream flag. Call [`EncryptWriter::finish`]
+/// to seal the last segment; dropping the writer without it leaves a stream
+/// that will fail authentication.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    cipher: SegmentCipher,
+    prefix: Vec<u8>,
+    aad: Vec<u8>,
+    segment_size: usize,
+    buf: Vec<u8>,
+    counter: u32,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(inner: W, cipher: SegmentCipher, prefix: &[u8], segment_size: u32, aad: &[u8]) -> Self {
+        let segment_size = segment_size as usize;
+        Self {
+            inner,
+            cipher,
+            prefix: prefix.to_vec(),
+            aad: aad.to_vec(),
+            segment_size,
+            buf: Vec::with_capacity(segment_size + TAG_LEN),
+            counter: 0,
+        }
+    }
+
+    fn seal_segment(&mut self, last: bool) -> io::Result<()> {
+        let nonce = segment_nonce(&self.prefix, self.counter, last as u8);
+        self.cipher
+            .encrypt_in_place(&nonce, &self.aad, &mut self.buf)
+            .map_err(|e| io::Error::other(format!("encryption failed: {e}")))?;
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        if !last {
+            self.counter = self
+                .counter
+                .checked_add(1)
+                .ok_or_else(|| io::Error::other("input too large for segment counter"))?;
+        }
+        Ok(())
+    }
+
+    /// Seals the final segment and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_segment(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        if self.buf.len() == self.segment_size {
+            self.seal_segment(false)?;
+        }
+        let take = data.len().min(self.segment_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..take]);
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Buffered plaintext is only emitted as sealed segments.
+        self.inner.flush()
+    }
+}
+
+/// Authenticates and decrypts a segment stream produced by [`EncryptWriter`].
+///
+/// Plaintext is released one authenticated segment at a time; a stream that
+/// ends without a valid final segment yields an error on the last read.
+pub struct DecryptReader<R: BufRead> {
+    inner: R,
+    cipher: SegmentCipher,
+    prefix: Vec<u8>,
+    aad: Vec<u8>,
+    segment_size: usize,
+    buf: Vec<u8>,
+    pos: usize,
+    counter: u32,
+    done: bool,
+}
+
+impl<R: BufRead> DecryptReader<R> {
+    pub fn new(inner: R, cipher: SegmentCipher, prefix: &[u8], segment_size: u32, aad: &[u8]) -> Self {
+        let segment_size = segment_size as usize;
+        Self {
+            inner,
+            cipher,
+            prefix: prefix.to_vec(),
+            aad: aad.to_vec(),
+            segment_size,
+            buf: Vec::with_capacity(segment_size + TAG_LEN),
+            pos: 0,
+            counter: 0,
+            done: false,
+        }
+    }
+
+    fn open_segment(&mut self) -> io::Result<()> {
+        self.buf.zeroize();
+        self.buf.resize(self.segment_size + TAG_LEN, 0);
+        let n = read_full(&mut self.inner, &mut self.buf)?;
+        self.buf.truncate(n);
+        self.pos = 0;
+        if n < TAG_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("ciphertext truncated at segment {}", self.counter),
+            ));
+        }
+        let last = n < self.segment_size + TAG_LEN || self.inner.fill_buf()?.is_empty();
+        open_in_place(&self.cipher, &self.prefix, &self.aad, self.counter, last, &mut self.buf)?;
+        if last {
+            self.done = true;
+        } else {
+            self.counter = self
+                .counter
+                .checked_add(1)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "segment counter overflow"))?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for DecryptReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.open_segment()?;
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> Drop for DecryptReader<R> {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+/// An inclusive plaintext byte range, written `START-END` or `START-` (to the
+/// end), as in an HTTP `Range: bytes=` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// Clamps the range to a plaintext of `len` bytes, returning the
+    /// half-open interval `[start, end)`.
+    pub fn resolve(&self, len: u64) -> io::Result<(u64, u64)> {
+        if self.start >= len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("range start {} is beyond the end of the plaintext ({len} bytes)", self.start),
+            ));
+        }
+        let end = self.end.map_or(len, |e| e.saturating_add(1).min(len));
+        Ok((self.start, end))
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("invalid range '{s}': expected START-END or START-");
+        let (start, end) = s.split_once('-').ok_or_else(bad)?;
+        let start = start.trim().parse().map_err(|_| bad())?;
+        let end = match end.trim() {
+            "" => None,
+            e => Some(e.parse().map_err(|_| bad())?),
+        };
+        match end {
+            Some(e) if e < start => Err(format!("invalid range '{s}': END is before START")),
+            _ => Ok(ByteRange { start, end }),
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}-{end}", self.start),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+/// Random access to a segment stream in a seekable source.
+///
+/// Segment `i` always starts at `i * (segment_size + TAG_LEN)`, so a range of
+/// plaintext can be served by seeking to and authenticating only the segments
+/// that cover it. The final segment is identified from the source length; a
+/// truncated stream therefore fails as soon as a range touches its end.
+pub struct SegmentedFile<R: Read + Seek> {
+    inner: R,
+    cipher: SegmentCipher,
+    prefix: Vec<u8>,
+    aad: Vec<u8>,
+    segment_size: u64,
+    data_start: u64,
+    segments: u64,
+    plaintext_len: u64,
+    buf: Vec<u8>,
+}
+
+impl<R: Read + Seek> SegmentedFile<R> {
+    /// `inner` must be positioned at the first segment.
+    pub fn new(mut inner: R, cipher: SegmentCipher, prefix: &[u8], segment_size: u32, aad: &[u8]) -> io::Result<Self> {
+        let data_start = inner.stream_position()?;
+        let ciphertext_len = inner.seek(SeekFrom::End(0))?.saturating_sub(data_start);
+        let segment_ct = segment_size as u64 + TAG_LEN as u64;
+        let segments = ciphertext_len.div_ceil(segment_ct);
+        if segments == 0 || ciphertext_len - (segments - 1) * segment_ct < TAG_LEN as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("ciphertext truncated at segment {}", segments.saturating_sub(1)),
+            ));
+        }
+        Ok(Self {
+            inner,
+            cipher,
+            prefix: prefix.to_vec(),
+            aad: aad.to_vec(),
+            segment_size: segment_size as u64,
+            data_start,
+            segments,
+            plaintext_len: ciphertext_len - segments * TAG_LEN as u64,
+            buf: Vec::with_capacity(segment_ct as usize),
+        })
+    }
+
+    pub fn plaintext_len(&self) -> u64 {
+        self.plaintext_len
+    }
+
+    /// Hides trailing padding: ranges are resolved against the first `len`
+    /// plaintext bytes only.
+    pub fn limit_plaintext(&mut self, len: u64) -> io::Result<()> {
+        if len > self.plaintext_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("ciphertext truncated: {} plaintext bytes, header records {len}", self.plaintext_len),
+            ));
+        }
+        self.plaintext_len = len;
+        Ok(())
+    }
+
+    /// Authenticates the segments covering `range` and writes its plaintext to
+    /// `out`. Returns the resolved half-open interval.
+    pub fn write_range<W: Write>(&mut self, range: ByteRange, mut out: W) -> io::Result<(u64, u64)> {
+        let (start, end) = range.resolve(self.plaintext_len)?;
+        let segment_ct = self.segment_size + TAG_LEN as u64;
+        for index in start / self.segment_size..=(end - 1) / self.segment_size {
+            let counter = u32::try_from(index)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "segment counter overflow"))?;
+            let last = index == self.segments - 1;
+            self.inner.seek(SeekFrom::Start(self.data_start + index * segment_ct))?;
+            self.buf.zeroize();
+            self.buf.resize(segment_ct as usize, 0);
+            let n = read_full(&mut self.inner, &mut self.buf)?;
+            self.buf.truncate(n);
+            open_in_place(&self.cipher, &self.prefix, &self.aad, counter, last, &mut self.buf)?;
+
+            let seg_start = index * self.segment_size;
+            let from = (start.max(seg_start) - seg_start) as usize;
+            let to = (end.min(seg_start + self.segment_size) - seg_start) as usize;
+            out.write_all(&self.buf[from..to.min(self.buf.len())])?;
+        }
+        out.flush()?;
+        Ok((start, end))
+    }
+}
+
+impl<R: Read + Seek> Drop for SegmentedFile<R> {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::CipherId;
+    use std::io::Cursor;
+
+    const SEG: u32 = 32;
+
+    fn seal_with(id: CipherId, plaintext: &[u8]) -> Vec<u8> {
+        let prefix = vec![7; id.nonce_prefix_len()];
+        let mut w = EncryptWriter::new(Vec::new(), SegmentCipher::new(id, &[42; 32]), &prefix, SEG, b"aad");
+        w.write_all(plaintext).unwrap();
+        w.finish().unwrap()
+    }
+
+    fn open_with(id: CipherId, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let prefix = vec![7; id.nonce_prefix_len()];
+        let cipher = SegmentCipher::new(id, &[42; 32]);
+        let mut r = DecryptReader::new(Cursor::new(ciphertext), cipher, &prefix, SEG, b"aad");
+        let mut out = Vec::new();
+        r.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn seal(plaintext: &[u8]) -> Vec<u8> {
+        seal_with(CipherId::Aes256Gcm, plaintext)
+    }
+
+    fn open(ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        open_with(CipherId::Aes256Gcm, ciphertext)
+    }
+
+    #[test]
+    fn round_trips_across_segment_boundaries() {
+        for id in [CipherId::Aes256Gcm, CipherId::XChaCha20Poly1305] {
+            for len in [0usize, 1, 31, 32, 33, 64, 100] {
+                let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                let sealed = seal_with(id, &data);
+                let segments = len.div_ceil(SEG as usize).max(1);
+                assert_eq!(sealed.len(), len + segments * TAG_LEN);
+                assert_eq!(open_with(id, &sealed).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn ciphers_are_not_interchangeable() {
+        let xchacha = seal_with(CipherId::XChaCha20Poly1305, b"segment");
+        let gcm = seal(b"segment");
+        assert!(open(&xchacha).is_err());
+        assert!(open_with(CipherId::XChaCha20Poly1305, &gcm).is_err());
+    }
+
+    #[test]
+    fn header_tag_binds_header_bytes() {
+        let cipher = SegmentCipher::new(CipherId::Aes256Gcm, &[42; 32]);
+        let prefix = [7u8; 7];
+        let tag = header_tag(&cipher, &prefix, b"header").unwrap();
+        assert_eq!(tag.len(), TAG_LEN);
+        assert!(verify_header_tag(&cipher, &prefix, b"header", &tag));
+        assert!(!verify_header_tag(&cipher, &prefix, b"headex", &tag));
+        assert!(!verify_header_tag(&cipher, &[8u8; 7], b"header", &tag));
+    }
+
+    #[test]
+    fn header_fields_are_bound_to_their_type() {
+        let cipher = SegmentCipher::new(CipherId::Aes256Gcm, &[42; 32]);
+        let sealed = seal_header_field(&cipher, &[7; 7], 0x8002, b"length").unwrap();
+        assert_eq!(open_header_field(&cipher, &[7; 7], 0x8002, &sealed).unwrap(), b"length");
+        assert!(open_header_field(&cipher, &[7; 7], 0x0003, &sealed).is_none());
+    }
+
+    #[test]
+    fn detects_truncation_at_segment_boundary() {
+        let sealed = seal(&[1u8; 100]);
+        let seg_ct = SEG as usize + TAG_LEN;
+        assert!(open(&sealed[..2 * seg_ct]).is_err());
+        assert!(open(&sealed[..sealed.len() - 1]).is_err());
+        assert!(open(&[]).is_err());
+    }
+
+    #[test]
+    fn detects_reordered_and_duplicated_segments() {
+        let sealed = seal(&[9u8; 100]);
+        let seg_ct = SEG as usize + TAG_LEN;
+        let (a, rest) = sealed.split_at(seg_ct);
+        let (b, tail) = rest.split_at(seg_ct);
+
+        let swapped = [b, a, tail].concat();
+        assert!(open(&swapped).is_err());
+
+        let duplicated = [a, a, b, tail].concat();
+        assert!(open(&duplicated).is_err());
+    }
+
+    #[test]
+    fn range_reads_touch_only_covering_segments() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let mut sealed = seal(&data);
+        let open_range = |sealed: &[u8], range: &str| -> io::Result<Vec<u8>> {
+            let cipher = SegmentCipher::new(CipherId::Aes256Gcm, &[42; 32]);
+            let mut file = SegmentedFile::new(Cursor::new(sealed), cipher, &[7; 7], SEG, b"aad")?;
+            let mut out = Vec::new();
+            file.write_range(range.parse().unwrap(), &mut out)?;
+            Ok(out)
+        };
+        for (range, expected) in [("0-0", 0..1), ("30-33", 30..34), ("64-", 64..100), ("90-500", 90..100), ("0-99", 0..100)] {
+            assert_eq!(open_range(&sealed, range).unwrap(), data[expected].to_vec(), "{range}");
+        }
+        assert!(open_range(&sealed, "100-").is_err());
+
+        // Corrupting segment 2 leaves ranges inside segments 0 and 1 readable.
+        let seg_ct = SEG as usize + TAG_LEN;
+        sealed[2 * seg_ct + 1] ^= 1;
+        assert_eq!(open_range(&sealed, "10-40").unwrap(), data[10..41].to_vec());
+        let err = open_range(&sealed, "60-70").unwrap_err();
+        assert!(err.to_string().contains("segment 2"), "{err}");
+
+        // Dropping the final segment makes the new last one fail its flag.
+        let intact = seal(&data);
+        assert!(open_range(&intact[..3 * seg_ct], "90-").is_err());
+        assert!(open_range(&intact[..2 * seg_ct], "20-").is_err());
+        assert_eq!(open_range(&intact[..2 * seg_ct], "0-31").unwrap(), data[..32].to_vec());
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!("5-9".parse(), Ok(ByteRange { start: 5, end: Some(9) }));
+        assert_eq!("5-".parse(), Ok(ByteRange { start: 5, end: None }));
+        assert!("9-5".parse::<ByteRange>().is_err());
+        assert!("-5".parse::<ByteRange>().is_err());
+        assert!("abc".parse::<ByteRange>().is_err());
+        assert_eq!(ByteRange { start: 5, end: None }.resolve(8).unwrap(), (5, 8));
+    }
+}
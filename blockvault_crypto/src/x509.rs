@@ -0,0 +1,928 @@
+//! X.509 certificates and CRLs (RFC 5280), enough to decide whether a CMS
+//! recipient's certificate may be encrypted to. Nothing is fetched over the
+//! network: the operator supplies a trust store and CRLs as PEM or DER files
+//! (or directories of them), and a certificate is accepted only if
+//!
+//! * it chains, through any intermediates shipped in the same file, to a
+//!   certificate in the trust store, with every signature verifying;
+//! * every certificate on the path is within its validity period;
+//! * every issuer is a CA (basicConstraints, keyCertSign, path length);
+//! * its key usage allows key transport (RSA) or key agreement (X25519), and
+//!   its extended key usage, if any, includes email protection;
+//! * a current CRL signed by each issuer on the path does not list it.
+//!
+//! Signatures may be RSA PKCS#1 v1.5 with SHA-256/384/512, or Ed25519. SHA-1
+//! and ECDSA are refused, as are critical extensions this module does not
+//! process (name or policy constraints, partitioned or delta CRLs, ...).
+//! Names are compared by their DER encoding.
+//!
+//! The DER reader and writer here are shared with `cms`. The reader also
+//! takes the BER indefinite lengths that streaming CMS producers emit.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::STANDARD as B64;
+use base64::Engine as _;
+use ed25519_dalek::{Signature, VerifyingKey};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const OCTET_STRING: u8 = 0x04;
+pub const OID: u8 = 0x06;
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+const BOOLEAN: u8 = 0x01;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+// OIDs, as the contents of their DER encoding.
+const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01]; // 1.2.840.113549.1.1.1
+const SHA1_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05];
+const SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01]; // 1.2.840.10045.2.1
+const ECDSA_WITH: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04]; // 1.2.840.10045.4, prefix of ecdsa-with-*
+pub const X25519: &[u8] = &[0x2b, 0x65, 0x6e]; // 1.3.101.110
+const ED25519: &[u8] = &[0x2b, 0x65, 0x70]; // 1.3.101.112
+const SUBJECT_KEY_ID: &[u8] = &[0x55, 0x1d, 0x0e];
+const KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const CRL_NUMBER: &[u8] = &[0x55, 0x1d, 0x14];
+const REASON_CODE: &[u8] = &[0x55, 0x1d, 0x15];
+const CERTIFICATE_POLICIES: &[u8] = &[0x55, 0x1d, 0x20];
+const AUTHORITY_KEY_ID: &[u8] = &[0x55, 0x1d, 0x23];
+const EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const ANY_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25, 0x00];
+const EMAIL_PROTECTION: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04]; // 1.3.6.1.5.5.7.3.4
+
+// keyUsage bits, numbered as in RFC 5280.
+const KEY_ENCIPHERMENT: u16 = 1 << 2;
+const KEY_AGREEMENT: u16 = 1 << 4;
+const KEY_CERT_SIGN: u16 = 1 << 5;
+const CRL_SIGN: u16 = 1 << 6;
+
+/// Most certificates followed from a recipient to a trust anchor.
+const MAX_PATH: usize = 8;
+/// Deepest nesting of BER indefinite-length elements accepted.
+const MAX_NESTING: usize = 32;
+
+/// A cursor over DER (or BER with indefinite lengths) elements.
+#[derive(Debug, Clone, Copy)]
+pub struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Der { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn peek(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// The next element as (tag, contents, whole encoding).
+    pub fn any(&mut self) -> Result<(u8, &'a [u8], &'a [u8])> {
+        self.element(0)
+    }
+
+    fn element(&mut self, depth: usize) -> Result<(u8, &'a [u8], &'a [u8])> {
+        let truncated = || anyhow!("truncated DER");
+        let data = self.data;
+        let &tag = data.first().ok_or_else(truncated)?;
+        if tag & 0x1f == 0x1f {
+            bail!("DER tag numbers above 30 are not supported");
+        }
+        let &first = data.get(1).ok_or_else(truncated)?;
+        let (start, len) = match first {
+            0x00..=0x7f => (2, Some(first as usize)),
+            0x80 => (2, None),
+            0x81..=0x84 => {
+                let n = (first & 0x7f) as usize;
+                let bytes = data.get(2..2 + n).ok_or_else(truncated)?;
+                (2 + n, Some(bytes.iter().fold(0usize, |len, &b| len << 8 | b as usize)))
+            }
+            _ => bail!("DER length too long"),
+        };
+        let (contents, end) = match len {
+            Some(len) => {
+                let end = start.checked_add(len).filter(|&end| end <= data.len()).ok_or_else(truncated)?;
+                (&data[start..end], end)
+            }
+            None if tag & 0x20 == 0 || depth >= MAX_NESTING => bail!("bad BER indefinite length"),
+            None => {
+                let mut inner = Der::new(&data[start..]);
+                while !inner.data.starts_with(&[0, 0]) {
+                    inner.element(depth + 1)?;
+                }
+                let end = data.len() - inner.data.len();
+                (&data[start..end], end + 2)
+            }
+        };
+        self.data = &data[end..];
+        Ok((tag, contents, &data[..end]))
+    }
+
+    /// Contents of the next element, which must have `tag`.
+    pub fn take(&mut self, tag: u8) -> Result<&'a [u8]> {
+        let (found, contents, _) = self.any()?;
+        if found != tag {
+            bail!("expected DER tag {tag:#04x}, found {found:#04x}");
+        }
+        Ok(contents)
+    }
+
+    /// Like `take`, for an element that may be absent.
+    pub fn optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>> {
+        if self.peek() == Some(tag) { self.take(tag).map(Some) } else { Ok(None) }
+    }
+
+    /// Whole encoding of the next element, which must have `tag`.
+    pub fn raw(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.any()? {
+            (found, _, whole) if found == tag => Ok(whole),
+            (found, _, _) => bail!("expected DER tag {tag:#04x}, found {found:#04x}"),
+        }
+    }
+
+    pub fn sequence(&mut self) -> Result<Der<'a>> {
+        self.take(SEQUENCE).map(Der::new)
+    }
+
+    /// A small non-negative INTEGER: a version or path length.
+    pub fn small_uint(&mut self) -> Result<u32> {
+        match self.take(INTEGER)? {
+            [] => bail!("bad DER integer"),
+            [first, ..] if first & 0x80 != 0 => bail!("negative DER integer"),
+            bytes => match bytes.strip_prefix(&[0]).unwrap_or(bytes) {
+                digits if digits.len() > 4 => bail!("DER integer too large"),
+                digits => Ok(digits.iter().fold(0u32, |n, &b| n << 8 | b as u32)),
+            },
+        }
+    }
+
+    /// An AlgorithmIdentifier: its OID and the whole encoding of its
+    /// parameters, if present.
+    pub fn algorithm(&mut self) -> Result<(&'a [u8], Option<&'a [u8]>)> {
+        let mut alg = self.sequence()?;
+        let oid = alg.take(OID)?;
+        let params = if alg.is_empty() { None } else { Some(alg.any()?.2) };
+        alg.finish()?;
+        Ok((oid, params))
+    }
+
+    /// Fails if anything is left.
+    pub fn finish(self) -> Result<()> {
+        if !self.data.is_empty() {
+            bail!("unexpected trailing DER data");
+        }
+        Ok(())
+    }
+}
+
+/// DER encoding of an element with `tag` and `contents`.
+pub fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+/// A constructed element (SEQUENCE, context tag, ...) of encoded `parts`.
+pub fn constructed(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    tlv(tag, &parts.concat())
+}
+
+/// An AlgorithmIdentifier for `oid` with already-encoded `params`.
+pub fn algorithm_id(oid: &[u8], params: Option<&[u8]>) -> Vec<u8> {
+    constructed(SEQUENCE, &[&tlv(OID, oid), params.unwrap_or_default()])
+}
+
+/// Bytes of a BIT STRING that must not have unused bits (keys, signatures).
+pub fn bit_string(contents: &[u8]) -> Result<&[u8]> {
+    contents.strip_prefix(&[0]).ok_or_else(|| anyhow!("bad DER bit string"))
+}
+
+/// Dotted form of an OID's contents, for messages.
+pub fn oid_string(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for &b in oid {
+        arc = arc << 7 | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.extend([first, arc - first * 40]);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// The DER bodies of the PEM blocks in `data` labelled with one of
+/// `labels`, or `data` itself if it is not PEM.
+pub fn pem_blocks(data: &[u8], labels: &[&str]) -> Result<Vec<Vec<u8>>> {
+    let text = data.trim_ascii_start();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !text.starts_with(b"-----BEGIN ") {
+        return Ok(vec![data.to_vec()]);
+    }
+    let text = std::str::from_utf8(text).map_err(|_| anyhow!("PEM file is not text"))?;
+    let mut blocks = Vec::new();
+    let mut body: Option<(&str, String)> = None;
+    for line in text.lines().map(str::trim) {
+        match &mut body {
+            None => {
+                if let Some(label) = line.strip_prefix("-----BEGIN ").and_then(|l| l.strip_suffix("-----")) {
+                    body = Some((label, String::new()));
+                }
+            }
+            Some((label, b64)) if line.strip_prefix("-----END ").and_then(|l| l.strip_suffix("-----")) == Some(label) => {
+                if labels.contains(label) {
+                    blocks.push(B64.decode(b64.as_bytes()).map_err(|e| anyhow!("bad base64 in PEM {label}: {e}"))?);
+                }
+                body = None;
+            }
+            Some((_, b64)) => b64.push_str(line),
+        }
+    }
+    if let Some((label, _)) = body {
+        bail!("PEM {label} block is not terminated");
+    }
+    Ok(blocks)
+}
+
+/// Seconds since the Unix epoch of a UTCTime or GeneralizedTime (UTC, whole
+/// seconds, as RFC 5280 requires).
+fn time(der: &mut Der) -> Result<i64> {
+    let (tag, contents, _) = der.any()?;
+    let bad = || anyhow!("bad certificate time '{}'", String::from_utf8_lossy(contents));
+    match (tag, contents.len()) {
+        (UTC_TIME, 13) | (GENERALIZED_TIME, 15) => {}
+        (UTC_TIME | GENERALIZED_TIME, _) => return Err(bad()),
+        _ => bail!("expected a time, found DER tag {tag:#04x}"),
+    }
+    let (digits, zone) = contents.split_at(contents.len() - 1);
+    if zone != b"Z" || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(bad());
+    }
+    let number = |range: std::ops::Range<usize>| digits[range].iter().fold(0i64, |n, &d| n * 10 + (d - b'0') as i64);
+    let (year, offset) = match tag {
+        UTC_TIME => match number(0..2) {
+            yy if yy < 50 => (2000 + yy, 2),
+            yy => (1900 + yy, 2),
+        },
+        _ => (number(0..4), 4),
+    };
+    let field = |i: usize| number(offset + 2 * i..offset + 2 * i + 2);
+    let (month, day, hour, minute, second) = (field(0), field(1), field(2), field(3), field(4));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return Err(bad());
+    }
+    Ok(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// `YYYY-MM-DD` of a Unix time, for messages.
+fn date(secs: i64) -> String {
+    let days = secs.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    format!("{:04}-{month:02}-{day:02}", year_of_era + era * 400 + i64::from(month <= 2))
+}
+
+/// `O=Acme, CN=alice` rendering of a DER Name, for messages.
+pub fn name_string(name: &[u8]) -> String {
+    fn render(name: &[u8]) -> Result<String> {
+        let mut rdns = Der::new(name).sequence()?;
+        let mut parts = Vec::new();
+        while !rdns.is_empty() {
+            let mut rdn = Der::new(rdns.take(SET)?);
+            while !rdn.is_empty() {
+                let mut attribute = rdn.sequence()?;
+                let oid = attribute.take(OID)?;
+                let (tag, value, _) = attribute.any()?;
+                let key = match oid {
+                    [0x55, 0x04, 0x03] => "CN".into(),
+                    [0x55, 0x04, 0x05] => "serialNumber".into(),
+                    [0x55, 0x04, 0x06] => "C".into(),
+                    [0x55, 0x04, 0x07] => "L".into(),
+                    [0x55, 0x04, 0x08] => "ST".into(),
+                    [0x55, 0x04, 0x0a] => "O".into(),
+                    [0x55, 0x04, 0x0b] => "OU".into(),
+                    [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".into(),
+                    _ => oid_string(oid),
+                };
+                let value = match tag {
+                    // BMPString
+                    0x1e => char::decode_utf16(value.chunks(2).map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect(),
+                    _ => String::from_utf8_lossy(value).into_owned(),
+                };
+                parts.push(format!("{key}={value}"));
+            }
+        }
+        Ok(parts.join(", "))
+    }
+    render(name).unwrap_or_else(|_| "<unreadable name>".into())
+}
+
+/// The subject public key of a certificate.
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    Rsa(RsaPublicKey),
+    X25519([u8; 32]),
+    Ed25519(VerifyingKey),
+    /// Any other algorithm, by name: usable neither as a recipient nor as
+    /// an issuer here.
+    Other(String),
+}
+
+impl PublicKey {
+    fn from_spki(spki: &[u8]) -> Result<Self> {
+        let mut der = Der::new(spki).sequence()?;
+        let (oid, _) = der.algorithm()?;
+        let bits = bit_string(der.take(BIT_STRING)?)?;
+        der.finish()?;
+        Ok(match oid {
+            RSA_ENCRYPTION => PublicKey::Rsa(RsaPublicKey::from_pkcs1_der(bits).map_err(|e| anyhow!("bad RSA key: {e}"))?),
+            X25519 => PublicKey::X25519(bits.try_into().map_err(|_| anyhow!("bad X25519 key"))?),
+            ED25519 => PublicKey::Ed25519(
+                VerifyingKey::from_bytes(bits.try_into().map_err(|_| anyhow!("bad Ed25519 key"))?)
+                    .map_err(|e| anyhow!("bad Ed25519 key: {e}"))?,
+            ),
+            EC_PUBLIC_KEY => PublicKey::Other("EC".into()),
+            _ => PublicKey::Other(oid_string(oid)),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            PublicKey::Rsa(_) => "RSA",
+            PublicKey::X25519(_) => "X25519",
+            PublicKey::Ed25519(_) => "Ed25519",
+            PublicKey::Other(name) => name,
+        }
+    }
+
+    /// Checks `signature` over `signed` under `algorithm`, a whole
+    /// AlgorithmIdentifier.
+    fn verify(&self, algorithm: &[u8], signed: &[u8], signature: &[u8]) -> Result<()> {
+        let (oid, params) = Der::new(algorithm).algorithm()?;
+        let bad = || anyhow!("signature does not verify");
+        let rsa = |key: &RsaPublicKey, scheme: Pkcs1v15Sign, digest: &[u8]| {
+            if params.is_some_and(|p| p != [0x05, 0x00]) {
+                bail!("unexpected RSA signature parameters");
+            }
+            key.verify(scheme, digest, signature).map_err(|_| bad())
+        };
+        match (oid, self) {
+            (SHA256_WITH_RSA, PublicKey::Rsa(key)) => rsa(key, Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(signed)),
+            (SHA384_WITH_RSA, PublicKey::Rsa(key)) => rsa(key, Pkcs1v15Sign::new::<Sha384>(), &Sha384::digest(signed)),
+            (SHA512_WITH_RSA, PublicKey::Rsa(key)) => rsa(key, Pkcs1v15Sign::new::<Sha512>(), &Sha512::digest(signed)),
+            (ED25519, PublicKey::Ed25519(key)) if params.is_none() => {
+                let signature = Signature::from_slice(signature).map_err(|_| bad())?;
+                key.verify_strict(signed, &signature).map_err(|_| bad())
+            }
+            (SHA1_WITH_RSA, _) => bail!("SHA-1 signatures are refused"),
+            (oid, _) if oid.starts_with(ECDSA_WITH) => bail!("ECDSA signatures are not supported"),
+            (oid, key) => bail!("unsupported signature algorithm {} for a {} key", oid_string(oid), key.name()),
+        }
+    }
+}
+
+/// Calls `each(oid, critical, value)` for every extension in `extensions`,
+/// the contents of an Extensions SEQUENCE.
+fn for_each_extension(extensions: &[u8], mut each: impl FnMut(&[u8], bool, &[u8]) -> Result<()>) -> Result<()> {
+    let mut list = Der::new(extensions).sequence()?;
+    let mut seen = Vec::new();
+    while !list.is_empty() {
+        let mut extension = list.sequence()?;
+        let oid = extension.take(OID)?;
+        let critical = match extension.optional(BOOLEAN)? {
+            None | Some([0x00]) => false,
+            Some([0xff]) => true,
+            Some(_) => bail!("bad DER boolean"),
+        };
+        let value = extension.take(OCTET_STRING)?;
+        extension.finish()?;
+        if seen.contains(&oid) {
+            bail!("extension {} appears twice", oid_string(oid));
+        }
+        seen.push(oid);
+        each(oid, critical, value)?;
+    }
+    Ok(())
+}
+
+fn unsupported_critical(oid: &[u8]) -> anyhow::Error {
+    anyhow!("unsupported critical extension {}", oid_string(oid))
+}
+
+/// A parsed X.509 v1-v3 certificate.
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    /// Whole encoding of the TBSCertificate, as signed.
+    tbs: Vec<u8>,
+    signature_algorithm: Vec<u8>,
+    signature: Vec<u8>,
+    /// Contents of the serialNumber INTEGER.
+    pub serial: Vec<u8>,
+    /// Whole encodings of the issuer and subject Names.
+    pub issuer: Vec<u8>,
+    pub subject: Vec<u8>,
+    not_before: i64,
+    not_after: i64,
+    pub key: PublicKey,
+    /// `Some(path length limit)` if basicConstraints marks a CA.
+    ca: Option<Option<u32>>,
+    key_usage: Option<u16>,
+    extended_key_usage: Option<Vec<Vec<u8>>>,
+}
+
+impl Certificate {
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let mut outer = Der::new(der);
+        let mut cert = outer.sequence()?;
+        outer.finish()?;
+        let tbs = cert.raw(SEQUENCE)?;
+        let signature_algorithm = cert.raw(SEQUENCE)?;
+        let signature = bit_string(cert.take(BIT_STRING)?)?;
+        cert.finish()?;
+
+        let mut fields = Der::new(tbs).sequence()?;
+        let version = match fields.optional(0xa0)? {
+            Some(version) => Der::new(version).small_uint()?,
+            None => 0,
+        };
+        if version > 2 {
+            bail!("unsupported certificate version {}", version + 1);
+        }
+        let serial = fields.take(INTEGER)?;
+        if fields.raw(SEQUENCE)? != signature_algorithm {
+            bail!("certificate signature algorithms disagree");
+        }
+        let issuer = fields.raw(SEQUENCE)?;
+        let mut validity = fields.sequence()?;
+        let (not_before, not_after) = (time(&mut validity)?, time(&mut validity)?);
+        validity.finish()?;
+        let subject = fields.raw(SEQUENCE)?;
+        let key = PublicKey::from_spki(fields.raw(SEQUENCE)?)?;
+        fields.optional(0x81)?; // issuerUniqueID
+        fields.optional(0x82)?; // subjectUniqueID
+        let mut cert = Certificate {
+            tbs: tbs.to_vec(),
+            signature_algorithm: signature_algorithm.to_vec(),
+            signature: signature.to_vec(),
+            serial: serial.to_vec(),
+            issuer: issuer.to_vec(),
+            subject: subject.to_vec(),
+            not_before,
+            not_after,
+            key,
+            ca: None,
+            key_usage: None,
+            extended_key_usage: None,
+        };
+        if let Some(extensions) = fields.optional(0xa3)? {
+            if version != 2 {
+                bail!("extensions in a version {} certificate", version + 1);
+            }
+            for_each_extension(extensions, |oid, critical, value| cert.extension(oid, critical, value))?;
+        }
+        fields.finish()?;
+        Ok(cert)
+    }
+
+    fn extension(&mut self, oid: &[u8], critical: bool, value: &[u8]) -> Result<()> {
+        let mut value = Der::new(value);
+        match oid {
+            BASIC_CONSTRAINTS => {
+                let mut constraints = value.sequence()?;
+                let ca = constraints.optional(BOOLEAN)? == Some(&[0xff]);
+                let limit = if constraints.is_empty() { None } else { Some(constraints.small_uint()?) };
+                constraints.finish()?;
+                self.ca = ca.then_some(limit);
+            }
+            KEY_USAGE => {
+                let bits = value.take(BIT_STRING)?;
+                let byte = |i: usize| bits.get(i).map_or(0, |b: &u8| b.reverse_bits() as u16);
+                self.key_usage = Some(byte(1) | byte(2) << 8);
+            }
+            EXT_KEY_USAGE => {
+                let mut purposes = value.sequence()?;
+                let mut oids = Vec::new();
+                while !purposes.is_empty() {
+                    oids.push(purposes.take(OID)?.to_vec());
+                }
+                self.extended_key_usage = Some(oids);
+            }
+            SUBJECT_KEY_ID | AUTHORITY_KEY_ID | SUBJECT_ALT_NAME | CERTIFICATE_POLICIES => return Ok(()),
+            _ if critical => return Err(unsupported_critical(oid)),
+            _ => return Ok(()),
+        }
+        value.finish()
+    }
+
+    /// Every certificate in a PEM or DER `data`, in order.
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Self>> {
+        pem_blocks(data, &["CERTIFICATE"])?.iter().map(|der| Self::from_der(der)).collect()
+    }
+
+    /// A recipient's certificate file: the recipient first, then any
+    /// intermediates.
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        let data = fs::read(path).map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?;
+        let certs = Self::parse_all(&data).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        if certs.is_empty() {
+            bail!("{} holds no certificate", path.display());
+        }
+        Ok(certs)
+    }
+
+    pub fn describe(&self) -> String {
+        name_string(&self.subject)
+    }
+
+    fn check_time(&self, now: i64) -> Result<()> {
+        if now < self.not_before || now > self.not_after {
+            bail!(
+                "{} is not valid now (valid from {} to {})",
+                self.describe(),
+                date(self.not_before),
+                date(self.not_after)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A parsed certificate revocation list.
+#[derive(Debug, Clone)]
+pub struct Crl {
+    tbs: Vec<u8>,
+    signature_algorithm: Vec<u8>,
+    signature: Vec<u8>,
+    issuer: Vec<u8>,
+    this_update: i64,
+    next_update: Option<i64>,
+    /// Serial numbers (INTEGER contents) of the revoked certificates.
+    revoked: Vec<Vec<u8>>,
+}
+
+impl Crl {
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let mut outer = Der::new(der);
+        let mut crl = outer.sequence()?;
+        outer.finish()?;
+        let tbs = crl.raw(SEQUENCE)?;
+        let signature_algorithm = crl.raw(SEQUENCE)?;
+        let signature = bit_string(crl.take(BIT_STRING)?)?;
+        crl.finish()?;
+
+        let mut fields = Der::new(tbs).sequence()?;
+        if fields.peek() == Some(INTEGER) && fields.small_uint()? != 1 {
+            bail!("unsupported CRL version");
+        }
+        if fields.raw(SEQUENCE)? != signature_algorithm {
+            bail!("CRL signature algorithms disagree");
+        }
+        let issuer = fields.raw(SEQUENCE)?;
+        let this_update = time(&mut fields)?;
+        let next_update = match fields.peek() {
+            Some(UTC_TIME | GENERALIZED_TIME) => Some(time(&mut fields)?),
+            _ => None,
+        };
+        let mut revoked = Vec::new();
+        if let Some(entries) = fields.optional(SEQUENCE)? {
+            let mut entries = Der::new(entries);
+            while !entries.is_empty() {
+                let mut entry = entries.sequence()?;
+                revoked.push(entry.take(INTEGER)?.to_vec());
+                time(&mut entry)?;
+                if !entry.is_empty() {
+                    for_each_extension(entry.raw(SEQUENCE)?, |oid, critical, _| match oid {
+                        _ if critical => Err(unsupported_critical(oid)),
+                        _ => Ok(()),
+                    })?;
+                }
+                entry.finish()?;
+            }
+        }
+        if let Some(extensions) = fields.optional(0xa0)? {
+            for_each_extension(extensions, |oid, critical, _| match oid {
+                CRL_NUMBER | AUTHORITY_KEY_ID | REASON_CODE => Ok(()),
+                _ if critical => Err(unsupported_critical(oid)),
+                _ => Ok(()),
+            })?;
+        }
+        fields.finish()?;
+        Ok(Crl {
+            tbs: tbs.to_vec(),
+            signature_algorithm: signature_algorithm.to_vec(),
+            signature: signature.to_vec(),
+            issuer: issuer.to_vec(),
+            this_update,
+            next_update,
+            revoked,
+        })
+    }
+
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Self>> {
+        pem_blocks(data, &["X509 CRL"])?.iter().map(|der| Self::from_der(der)).collect()
+    }
+
+    /// Checks that `issuer` signed this CRL and that it is current at `now`.
+    fn check(&self, issuer: &Certificate, now: i64) -> Result<()> {
+        if issuer.key_usage.is_some_and(|usage| usage & CRL_SIGN == 0) {
+            bail!("{} may not sign CRLs (keyUsage)", issuer.describe());
+        }
+        issuer
+            .key
+            .verify(&self.signature_algorithm, &self.tbs, &self.signature)
+            .map_err(|e| anyhow!("CRL from {}: {e}", issuer.describe()))?;
+        match self.next_update {
+            _ if now < self.this_update => bail!("CRL from {} is not valid until {}", issuer.describe(), date(self.this_update)),
+            Some(next) if now > next => bail!("CRL from {} expired on {}", issuer.describe(), date(next)),
+            None => bail!("CRL from {} has no nextUpdate", issuer.describe()),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Files named by a `--trust-store` / `--crl` path: the file itself, or
+/// every file in the directory.
+fn files_at(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = fs::read_dir(path)
+        .map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    files.retain(|file| file.is_file());
+    files.sort();
+    Ok(files)
+}
+
+/// Certificates trusted as issuers, and the CRLs chains are checked against.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    anchors: Vec<Certificate>,
+    crls: Vec<Crl>,
+}
+
+impl TrustStore {
+    /// Loads trust anchors from `trust_store` and CRLs from `crl`; each is a
+    /// PEM/DER file or a directory of them.
+    pub fn load(trust_store: &Path, crl: &Path) -> Result<Self> {
+        let mut store = TrustStore::default();
+        for file in files_at(trust_store)? {
+            let data = fs::read(&file).map_err(|e| anyhow!("cannot read {}: {e}", file.display()))?;
+            store.anchors.extend(Certificate::parse_all(&data).map_err(|e| anyhow!("{}: {e}", file.display()))?);
+        }
+        for file in files_at(crl)? {
+            let data = fs::read(&file).map_err(|e| anyhow!("cannot read {}: {e}", file.display()))?;
+            store.crls.extend(Crl::parse_all(&data).map_err(|e| anyhow!("{}: {e}", file.display()))?);
+        }
+        if store.anchors.is_empty() {
+            bail!("no certificates in trust store {}", trust_store.display());
+        }
+        if store.crls.is_empty() {
+            bail!("no CRLs in {}", crl.display());
+        }
+        Ok(store)
+    }
+
+    /// Checks that `chain[0]`, helped by the intermediates after it, may be
+    /// encrypted to at `now` (seconds since the Unix epoch); see the module
+    /// documentation for the rules.
+    pub fn validate(&self, chain: &[Certificate], now: i64) -> Result<()> {
+        let leaf = &chain[0];
+        let mut path = vec![leaf];
+        let anchor = loop {
+            let current = path[path.len() - 1];
+            if let Some(anchor) = signed_by(current, self.anchors.iter())? {
+                break anchor;
+            }
+            let unused = chain[1..].iter().filter(|c| !path.iter().any(|p| std::ptr::eq(*p, *c)));
+            match signed_by(current, unused)? {
+                Some(_) if path.len() >= MAX_PATH => bail!("certificate chain of {} is too long", leaf.describe()),
+                Some(issuer) => path.push(issuer),
+                None => bail!(
+                    "{} is not issued by a certificate in the trust store (issuer {})",
+                    current.describe(),
+                    name_string(&current.issuer)
+                ),
+            }
+        };
+
+        anchor.check_time(now)?;
+        for (depth, cert) in path.iter().enumerate() {
+            cert.check_time(now)?;
+            if depth > 0 {
+                check_issuer(cert, depth - 1)?;
+            }
+        }
+        if let Some(Some(limit)) = anchor.ca
+            && path.len() - 1 > limit as usize
+        {
+            bail!("{} allows at most {limit} intermediate certificates", anchor.describe());
+        }
+
+        let (usage, name) = match leaf.key {
+            PublicKey::Rsa(_) => (KEY_ENCIPHERMENT, "keyEncipherment"),
+            PublicKey::X25519(_) => (KEY_AGREEMENT, "keyAgreement"),
+            ref key => bail!("{} has an {} key; CMS recipients need RSA or X25519", leaf.describe(), key.name()),
+        };
+        if leaf.key_usage.is_some_and(|bits| bits & usage == 0) {
+            bail!("{} does not allow {name} (keyUsage)", leaf.describe());
+        }
+        if let Some(purposes) = &leaf.extended_key_usage
+            && !purposes.iter().any(|oid| oid == EMAIL_PROTECTION || oid == ANY_EXT_KEY_USAGE)
+        {
+            bail!("{} is not for email protection (extendedKeyUsage)", leaf.describe());
+        }
+
+        for (i, cert) in path.iter().enumerate() {
+            self.check_revocation(cert, path.get(i + 1).copied().unwrap_or(anchor), now)?;
+        }
+        Ok(())
+    }
+
+    fn check_revocation(&self, cert: &Certificate, issuer: &Certificate, now: i64) -> Result<()> {
+        let mut failure = None;
+        for crl in self.crls.iter().filter(|crl| crl.issuer == issuer.subject) {
+            match crl.check(issuer, now) {
+                Ok(()) if crl.revoked.contains(&cert.serial) => {
+                    bail!("{} (serial {}) is revoked", cert.describe(), hex::encode(&cert.serial))
+                }
+                Ok(()) => return Ok(()),
+                Err(e) => failure = Some(e),
+            }
+        }
+        Err(failure.unwrap_or_else(|| anyhow!("no CRL from {} to check {} against", issuer.describe(), cert.describe())))
+    }
+}
+
+/// The first of `candidates` named as `cert`'s issuer whose signature on it
+/// verifies. Fails if a candidate is named but none verifies.
+fn signed_by<'c>(cert: &Certificate, candidates: impl Iterator<Item = &'c Certificate>) -> Result<Option<&'c Certificate>> {
+    let mut failure = None;
+    for issuer in candidates.filter(|issuer| issuer.subject == cert.issuer) {
+        match issuer.key.verify(&cert.signature_algorithm, &cert.tbs, &cert.signature) {
+            Ok(()) => return Ok(Some(issuer)),
+            Err(e) => failure = Some(anyhow!("{}: {e}", cert.describe())),
+        }
+    }
+    failure.map_or(Ok(None), Err)
+}
+
+/// Checks that `cert` may issue certificates with `below` intermediates
+/// under it.
+fn check_issuer(cert: &Certificate, below: usize) -> Result<()> {
+    match cert.ca {
+        None => bail!("{} is not a CA certificate (basicConstraints)", cert.describe()),
+        Some(Some(limit)) if below > limit as usize => {
+            bail!("{} allows at most {limit} intermediate certificates", cert.describe())
+        }
+        Some(_) if cert.key_usage.is_some_and(|usage| usage & KEY_CERT_SIGN == 0) => {
+            bail!("{} may not sign certificates (keyUsage)", cert.describe())
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ed25519 root "Acme Test CA"; X25519 leaves bob (serial 1002) and carol
+    // (serial 1003), valid 2026-10-17 to 2046-10-12; a CRL revoking carol.
+    const CA: &str = "-----BEGIN CERTIFICATE-----
+MIIBcTCCASOgAwIBAgIUCefYjKgFesc3wp8KBHtXYySUD7AwBQYDK2VwMCYxDTAL
+BgNVBAoMBEFjbWUxFTATBgNVBAMMDEFjbWUgVGVzdCBDQTAeFw0yNjEwMTcyMjIy
+MjRaFw00NjEwMTIyMjIyMjRaMCYxDTALBgNVBAoMBEFjbWUxFTATBgNVBAMMDEFj
+bWUgVGVzdCBDQTAqMAUGAytlcAMhAOcjuoeuQbkG1oSImsj27LaTXyYFK+QM2cXL
+tLqWsfqSo2MwYTAdBgNVHQ4EFgQUYcu1djXT6urPotiejK4Ju8G9tPcwHwYDVR0j
+BBgwFoAUYcu1djXT6urPotiejK4Ju8G9tPcwDwYDVR0TAQH/BAUwAwEB/zAOBgNV
+HQ8BAf8EBAMCAQYwBQYDK2VwA0EAbMT8GyO2Bq1CZFChVmfGyiZ/wzlYfj05rNl+
+Jj0QBjSPPN2rwJvZWPKbQFsRNqZMFGHSaCOwbcWa1A6+ikUXDw==
+-----END CERTIFICATE-----";
+    const BOB: &str = "-----BEGIN CERTIFICATE-----
+MIIBUDCCAQKgAwIBAgICEAIwBQYDK2VwMCYxDTALBgNVBAoMBEFjbWUxFTATBgNV
+BAMMDEFjbWUgVGVzdCBDQTAeFw0yNjEwMTcyMjIyMjRaFw00NjEwMTIyMjIyMjRa
+MB0xDTALBgNVBAoMBEFjbWUxDDAKBgNVBAMMA2JvYjAqMAUGAytlbgMhALLdlQ8N
+kjZSiJyPB1NbZqLExmmR4LsJJbkoXDNGtjBjo10wWzAJBgNVHRMEAjAAMA4GA1Ud
+DwEB/wQEAwIDCDAdBgNVHQ4EFgQUnfAkUDTYpRpOXLNysoVr3cNCb4QwHwYDVR0j
+BBgwFoAUYcu1djXT6urPotiejK4Ju8G9tPcwBQYDK2VwA0EAZYbSFOKFSigxxg10
+0iyOR8F2EflZATMjzBJpzYjoAEup9UfuRAUIHbLCLbmGG8XjCaJSYCfdTCtL2TOE
+eZ3ZCw==
+-----END CERTIFICATE-----";
+    const CAROL: &str = "-----BEGIN CERTIFICATE-----
+MIIBUjCCAQSgAwIBAgICEAMwBQYDK2VwMCYxDTALBgNVBAoMBEFjbWUxFTATBgNV
+BAMMDEFjbWUgVGVzdCBDQTAeFw0yNjEwMTcyMjIyMjRaFw00NjEwMTIyMjIyMjRa
+MB8xDTALBgNVBAoMBEFjbWUxDjAMBgNVBAMMBWNhcm9sMCowBQYDK2VuAyEA2rhD
+vyrXZHVnbIYS50c2nyt8xWu/CuIYiix+1dexwHOjXTBbMAkGA1UdEwQCMAAwDgYD
+VR0PAQH/BAQDAgMIMB0GA1UdDgQWBBT5fTVE4WIxWPurjgutWmea2/aS2jAfBgNV
+HSMEGDAWgBRhy7V2NdPq6s+i2J6Mrgm7wb209zAFBgMrZXADQQDG7AWLHxkoTP8i
+or+S8Hq7GhOKheJSB6R/MzyWc93p5N2MMjYyYZkkRhcwtBN892S5BI4wHVDNdRtu
+Sljq5PoH
+-----END CERTIFICATE-----";
+    const CRL: &str = "-----BEGIN X509 CRL-----
+MIHDMHcCAQEwBQYDK2VwMCYxDTALBgNVBAoMBEFjbWUxFTATBgNVBAMMDEFjbWUg
+VGVzdCBDQRcNMjYxMDE3MjIyMjI5WhcNNDYxMDEyMjIyMjI5WjAVMBMCAhADFw0y
+NjEwMTcyMjIyMjlaoA4wDDAKBgNVHRQEAwIBATAFBgMrZXADQQArgswwR/fGca66
+9WWY10Jm0t3eD7H/B40zLQNzTBjCvgYA/vfcxfpG9gq4qnw6mDPeTnf1BfLbsAJ1
+C4ns0hUA
+-----END X509 CRL-----";
+    /// 2030-03-17, inside every fixture's validity period.
+    const NOW: i64 = 1_900_000_000;
+
+    fn store(anchors: &str, crls: &str) -> TrustStore {
+        TrustStore {
+            anchors: Certificate::parse_all(anchors.as_bytes()).unwrap(),
+            crls: Crl::parse_all(crls.as_bytes()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_chain_to_the_trust_store_and_refuses_revoked_or_expired_certificates() {
+        let bob = Certificate::parse_all(BOB.as_bytes()).unwrap();
+        let carol = Certificate::parse_all(CAROL.as_bytes()).unwrap();
+        assert_eq!(bob[0].describe(), "O=Acme, CN=bob");
+        assert!(matches!(bob[0].key, PublicKey::X25519(_)));
+
+        let trusted = store(CA, CRL);
+        trusted.validate(&bob, NOW).unwrap();
+        let revoked = trusted.validate(&carol, NOW).unwrap_err().to_string();
+        assert!(revoked.contains("O=Acme, CN=carol (serial 1003) is revoked"), "{revoked}");
+        let expired = trusted.validate(&bob, 2_500_000_000).unwrap_err().to_string();
+        assert!(expired.contains("not valid now (valid from 2026-10-17 to 2046-10-12)"), "{expired}");
+
+        // An unknown issuer, a missing CRL and a forged leaf all fail.
+        let untrusted = store(BOB, CRL).validate(&carol, NOW).unwrap_err().to_string();
+        assert!(untrusted.contains("not issued by a certificate in the trust store"), "{untrusted}");
+        let no_crl = store(CA, "").validate(&bob, NOW).unwrap_err().to_string();
+        assert!(no_crl.contains("no CRL from O=Acme, CN=Acme Test CA"), "{no_crl}");
+        let mut forged = bob.clone();
+        let last = forged[0].tbs.len() - 1;
+        forged[0].tbs[last] ^= 1;
+        assert!(trusted.validate(&forged, NOW).unwrap_err().to_string().contains("signature does not verify"));
+    }
+
+    #[test]
+    fn reads_both_time_encodings_and_indefinite_lengths() {
+        assert_eq!(time(&mut Der::new(&tlv(UTC_TIME, b"700101000000Z"))).unwrap(), 0);
+        assert_eq!(time(&mut Der::new(&tlv(GENERALIZED_TIME, b"20300317174640Z"))).unwrap(), NOW);
+        assert_eq!(date(NOW), "2030-03-17");
+        assert!(time(&mut Der::new(&tlv(UTC_TIME, b"7001010000Z"))).is_err());
+
+        // SEQUENCE (indefinite) { OCTET STRING "hi" } followed by NULL.
+        let ber = [0x30, 0x80, 0x04, 0x02, b'h', b'i', 0x00, 0x00, 0x05, 0x00];
+        let mut der = Der::new(&ber);
+        let mut inner = der.sequence().unwrap();
+        assert_eq!(inner.take(OCTET_STRING).unwrap(), b"hi");
+        inner.finish().unwrap();
+        assert_eq!(der.take(0x05).unwrap(), b"");
+        assert!(Der::new(&ber[..6]).any().is_err());
+    }
+}
@@ -0,0 +1,39 @@
+import os
+import shutil
+import subprocess
+from pathlib import Path
+
+import pytest
+
+from blockvault.core import crypto_cli
+
+ROOT = Path(__file__).resolve().parent.parent
+
+
+@pytest.fixture(scope="module", autouse=True)
+def engine():
+    if not os.getenv("BLOCKVAULT_CRYPTO_BIN"):
+        if not shutil.which("cargo"):
+            pytest.skip("needs cargo or BLOCKVAULT_CRYPTO_BIN")
+        subprocess.run(["cargo", "build", "-q"], cwd=ROOT / "blockvault_crypto", check=True)
+        os.environ["BLOCKVAULT_CRYPTO_BIN"] = str(ROOT / "blockvault_crypto/target/debug/blockvault_crypto")
+
+
+def test_range_request_on_compressed_upload_returns_the_whole_file(tmp_path):
+    data = b"BlockVault " * 5000
+    plain, enc, out = tmp_path / "plain", tmp_path / "enc", tmp_path / "out"
+    plain.write_bytes(data)
+    crypto_cli.encrypt_file(plain, enc, "hunter2", None, compress="zstd")
+
+    assert crypto_cli.decrypt_file(enc, out, "hunter2", None, "0-99") is None
+    assert out.read_bytes() == data
+
+
+def test_range_request_on_plain_upload_is_partial(tmp_path):
+    data = bytes(range(256)) * 100
+    plain, enc, out = tmp_path / "plain", tmp_path / "enc", tmp_path / "out"
+    plain.write_bytes(data)
+    crypto_cli.encrypt_file(plain, enc, "hunter2", None)
+
+    assert crypto_cli.decrypt_file(enc, out, "hunter2", None, "100-199") == (100, 199, len(data))
+    assert out.read_bytes() == data[100:200]